@@ -0,0 +1,682 @@
+// A read-only bitmap that queries the portable Roaring format in place, without
+// decoding its containers.
+use std::io;
+
+use crate::serialization::{invalid_data, read_u16, read_u32, reader_at, NO_OFFSET_THRESHOLD, SERIAL_COOKIE, SERIAL_COOKIE_NO_RUNCONTAINER};
+use crate::{ArrayContainer, BitmapContainer, Container, ContainerType, RoaringBitmap, RunContainer};
+
+/// A read-only view of a bitmap serialized by [`RoaringBitmap::serialize_into`].
+///
+/// Loading only parses the container headers; values are read straight from the
+/// borrowed bytes on every query. Container payloads are checked to lie within the
+/// buffer but their contents are trusted.
+#[derive(Debug)]
+pub struct FrozenRoaringBitmap<'a> {
+    containers: Vec<FrozenContainer<'a>>,
+}
+
+#[derive(Debug)]
+struct FrozenContainer<'a> {
+    most_significant_bits: u16,
+    cardinality: usize,
+    container: FrozenContainerType<'a>,
+}
+
+// Each variant borrows the little-endian payload of its container: the values of
+// an array, the 1024 words of a bitmap, or the (start, length) pairs of a run
+// container without its leading run count.
+#[derive(Debug)]
+enum FrozenContainerType<'a> {
+    Array(&'a [u8]),
+    Bitmap(&'a [u8]),
+    Run(&'a [u8]),
+}
+
+impl<'a> FrozenRoaringBitmap<'a> {
+    /// Creates a view over `bytes`, which must hold a bitmap in the portable Roaring
+    /// format. Takes time proportional to the number of containers.
+    pub fn from_bytes(bytes: &'a [u8]) -> io::Result<Self> {
+        let cookie = read_u32(&mut reader_at(bytes, 0))?;
+        let (size, run_flags, descriptions_start) = if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+            (read_u32(&mut reader_at(bytes, 4))? as usize, None, 8)
+        } else if cookie as u16 == SERIAL_COOKIE {
+            let size = (cookie >> 16) as usize + 1;
+            let run_flags = slice(bytes, 4, size.div_ceil(8))?;
+            (size, Some(run_flags), 4 + run_flags.len())
+        } else {
+            return Err(invalid_data("unknown cookie"));
+        };
+        if size > 1 << 16 {
+            return Err(invalid_data("more than 2^16 containers"));
+        }
+
+        let descriptions = slice(bytes, descriptions_start, 4 * size)?;
+        let has_offsets = run_flags.is_none() || size >= NO_OFFSET_THRESHOLD;
+        let offsets_start = descriptions_start + descriptions.len();
+        let mut offset = offsets_start + if has_offsets { 4 * size } else { 0 };
+
+        let mut containers: Vec<FrozenContainer<'a>> = Vec::with_capacity(size);
+        for index in 0..size {
+            let most_significant_bits = read_u16(&mut reader_at(descriptions, 4 * index))?;
+            let cardinality = read_u16(&mut reader_at(descriptions, 4 * index + 2))? as usize + 1;
+            if containers.last().is_some_and(|previous| previous.most_significant_bits >= most_significant_bits) {
+                return Err(invalid_data("container keys are not strictly increasing"));
+            }
+            if has_offsets {
+                offset = read_u32(&mut reader_at(bytes, offsets_start + 4 * index))? as usize;
+            }
+
+            let is_run = run_flags.is_some_and(|run_flags| run_flags[index / 8] & (1 << (index % 8)) != 0);
+            let container = if is_run {
+                let number_of_runs = read_u16(&mut reader_at(bytes, offset))? as usize;
+                let runs = slice(bytes, offset + 2, 4 * number_of_runs)?;
+                offset += 2 + runs.len();
+                FrozenContainerType::Run(runs)
+            } else if cardinality <= 4096 {
+                let array = slice(bytes, offset, 2 * cardinality)?;
+                offset += array.len();
+                FrozenContainerType::Array(array)
+            } else {
+                let bitmap = slice(bytes, offset, BitmapContainer::SIZE_IN_BYTES)?;
+                offset += bitmap.len();
+                FrozenContainerType::Bitmap(bitmap)
+            };
+            containers.push(FrozenContainer { most_significant_bits, cardinality, container });
+        }
+        Ok(FrozenRoaringBitmap { containers })
+    }
+
+    /// Returns `true` if `value` is in the bitmap.
+    pub fn contains(&self, value: u32) -> bool {
+        let most_significant_bits = (value >> 16) as u16;
+        let least_significant_bits = value as u16;
+        match self.containers.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
+            Ok(index) => self.containers[index].container.contains(least_significant_bits),
+            Err(_) => false
+        }
+    }
+
+    /// Returns `true` if the bitmap holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.containers.is_empty()
+    }
+
+    /// Returns the number of values in the bitmap, as recorded in the container headers.
+    pub fn len(&self) -> u64 {
+        self.containers.iter().map(|container| container.cardinality as u64).sum()
+    }
+
+    /// Returns the number of values in the bitmap that are less than or equal to `value`.
+    pub fn rank(&self, value: u32) -> u64 {
+        let most_significant_bits = (value >> 16) as u16;
+        let least_significant_bits = value as u16;
+        let (preceding, rank_in_container) =
+            match self.containers.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
+                Ok(index) => (index, self.containers[index].container.rank(least_significant_bits) as u64),
+                Err(index) => (index, 0),
+            };
+        let preceding_len: u64 = self.containers[..preceding].iter().map(|container| container.cardinality as u64).sum();
+        preceding_len + rank_in_container
+    }
+
+    /// Returns an iterator over the values of the bitmap in ascending order.
+    pub fn iter(&self) -> FrozenIter<'_> {
+        FrozenIter {
+            containers: self.containers.iter(),
+            current: None,
+        }
+    }
+
+    /// Returns the values that are in `self`, `rhs` or both.
+    pub fn union(&self, rhs: &RoaringBitmap) -> RoaringBitmap {
+        let mut res = RoaringBitmap::new();
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < self.containers.len() && rhs_idx < rhs.data.len() {
+            let lhs_container = &self.containers[lhs_idx];
+            let rhs_container = &rhs.data[rhs_idx];
+            if lhs_container.most_significant_bits == rhs_container.most_significant_bits {
+                res.data.push(Container {
+                    most_significant_bits: lhs_container.most_significant_bits,
+                    container: lhs_container.container.union(lhs_container.most_significant_bits, &rhs_container.container),
+                });
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_container.most_significant_bits < rhs_container.most_significant_bits {
+                res.data.push(lhs_container.to_container());
+                lhs_idx += 1;
+            } else {
+                res.data.push(rhs_container.clone());
+                rhs_idx += 1;
+            }
+        }
+        while lhs_idx < self.containers.len() {
+            res.data.push(self.containers[lhs_idx].to_container());
+            lhs_idx += 1;
+        }
+        while rhs_idx < rhs.data.len() {
+            res.data.push(rhs.data[rhs_idx].clone());
+            rhs_idx += 1;
+        }
+        res
+    }
+
+    /// Returns the values that are in both `self` and `rhs`. Containers are read in
+    /// place, and only the result is allocated.
+    pub fn intersection(&self, rhs: &RoaringBitmap) -> RoaringBitmap {
+        let mut res = RoaringBitmap::new();
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < self.containers.len() && rhs_idx < rhs.data.len() {
+            let lhs_container = &self.containers[lhs_idx];
+            let rhs_container = &rhs.data[rhs_idx];
+            if lhs_container.most_significant_bits == rhs_container.most_significant_bits {
+                let container = lhs_container.container.intersection(lhs_container.most_significant_bits, &rhs_container.container);
+                if !container.is_empty() {
+                    res.data.push(Container {
+                        most_significant_bits: lhs_container.most_significant_bits,
+                        container,
+                    });
+                }
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_container.most_significant_bits < rhs_container.most_significant_bits {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        res
+    }
+}
+
+impl<'a> IntoIterator for &'a FrozenRoaringBitmap<'_> {
+    type Item = u32;
+    type IntoIter = FrozenIter<'a>;
+
+    fn into_iter(self) -> FrozenIter<'a> {
+        self.iter()
+    }
+}
+
+impl FrozenContainer<'_> {
+    fn to_container(&self) -> Container {
+        Container {
+            most_significant_bits: self.most_significant_bits,
+            container: self.to_container_type(),
+        }
+    }
+
+    // Copies the container out of the borrowed bytes. The header cardinality was
+    // never checked against the payload, so the owned container counts its values
+    // again instead of trusting it.
+    fn to_container_type(&self) -> ContainerType {
+        let most_significant_bits = self.most_significant_bits;
+        match self.container {
+            FrozenContainerType::Array(array) => ContainerType::ContainerTypeArray(ArrayContainer {
+                most_significant_bits,
+                array: (0..array.len() / 2).map(|index| u16_at(array, index)).collect(),
+            }),
+            FrozenContainerType::Bitmap(bitmap) => ContainerType::from_bitmap(BitmapContainer::from_words(
+                most_significant_bits,
+                Box::new(std::array::from_fn(|index| u64_at(bitmap, index))),
+            )),
+            FrozenContainerType::Run(runs) => {
+                let runs: Vec<(u16, u16)> = (0..runs.len() / 4).map(|index| run_at(runs, index)).collect();
+                let cardinality = runs.iter().map(|&(_, length)| length as usize + 1).sum();
+                ContainerType::ContainerTypeRun(RunContainer { most_significant_bits, runs, cardinality })
+            }
+        }
+    }
+}
+
+impl FrozenContainerType<'_> {
+    fn contains(&self, value: u16) -> bool {
+        match *self {
+            Self::Array(array) => {
+                let index = partition_point(array.len() / 2, |index| u16_at(array, index) < value);
+                index < array.len() / 2 && u16_at(array, index) == value
+            }
+            Self::Bitmap(bitmap) => u64_at(bitmap, value as usize / 64) & (1 << (value % 64)) != 0,
+            Self::Run(runs) => {
+                let index = partition_point(runs.len() / 4, |index| run_at(runs, index).0 <= value);
+                index > 0 && {
+                    let (start, length) = run_at(runs, index - 1);
+                    value as u32 <= start as u32 + length as u32
+                }
+            }
+        }
+    }
+
+    // Number of values in the container that are less than or equal to `value`.
+    fn rank(&self, value: u16) -> usize {
+        match *self {
+            Self::Array(array) => partition_point(array.len() / 2, |index| u16_at(array, index) <= value),
+            Self::Bitmap(bitmap) => {
+                let index = value as usize / 64;
+                let preceding: usize = (0..index).map(|index| u64_at(bitmap, index).count_ones() as usize).sum();
+                preceding + (u64_at(bitmap, index) << (63 - value % 64)).count_ones() as usize
+            }
+            Self::Run(runs) => {
+                let mut rank = 0;
+                for index in 0..runs.len() / 4 {
+                    let (start, length) = run_at(runs, index);
+                    if start > value {
+                        break;
+                    }
+                    rank += (value as usize).min(start as usize + length as usize) - start as usize + 1;
+                }
+                rank
+            }
+        }
+    }
+}
+
+// Set operations between a serialized container and an owned one with the same
+// key. Values and words are read from the bytes as they are needed, so only the
+// result is allocated.
+impl FrozenContainerType<'_> {
+    fn union(&self, most_significant_bits: u16, rhs: &ContainerType) -> ContainerType {
+        match (self, rhs) {
+            (_, ContainerType::ContainerTypeRun(run_container)) if run_container.is_full() => rhs.clone(),
+            (Self::Bitmap(_), _) | (_, ContainerType::ContainerTypeBitmap(_)) => {
+                let mut bitmap = Box::new([0u64; 1024]);
+                self.for_each_word(|index, word| bitmap[index] |= word);
+                rhs.for_each_word(|index, word| bitmap[index] |= word);
+                ContainerType::from_bitmap(BitmapContainer::from_words(most_significant_bits, bitmap))
+            }
+            (Self::Array(array), ContainerType::ContainerTypeArray(rhs)) => {
+                let mut res = Vec::with_capacity(array.len() / 2 + rhs.array.len());
+                let mut lhs_idx = 0;
+                let mut rhs_idx = 0;
+                while lhs_idx < array.len() / 2 || rhs_idx < rhs.array.len() {
+                    let value = if rhs_idx == rhs.array.len() || (lhs_idx < array.len() / 2 && u16_at(array, lhs_idx) <= rhs.array[rhs_idx]) {
+                        lhs_idx += 1;
+                        u16_at(array, lhs_idx - 1)
+                    } else {
+                        rhs_idx += 1;
+                        rhs.array[rhs_idx - 1]
+                    };
+                    if res.last() != Some(&value) {
+                        res.push(value);
+                    }
+                }
+                ContainerType::from_sorted_array(most_significant_bits, res)
+            }
+            // At least one side is a run container and the other holds no bitmap, so
+            // the values of an array are merged in as runs of one.
+            (_, rhs) => {
+                let mut res = RunContainer::new(most_significant_bits);
+                let mut lhs_runs = self.runs().peekable();
+                let mut rhs_runs = owned_runs(rhs).peekable();
+                loop {
+                    let run = match (lhs_runs.peek(), rhs_runs.peek()) {
+                        (Some(lhs_run), Some(rhs_run)) if rhs_run.0 < lhs_run.0 => rhs_runs.next(),
+                        (Some(_), _) => lhs_runs.next(),
+                        (None, _) => rhs_runs.next(),
+                    };
+                    let Some((start, end)) = run else { break };
+                    res.push_run(start, end);
+                }
+                ContainerType::from_run(res)
+            }
+        }
+    }
+
+    fn intersection(&self, most_significant_bits: u16, rhs: &ContainerType) -> ContainerType {
+        match (self, rhs) {
+            (Self::Array(array), _) => ContainerType::ContainerTypeArray(ArrayContainer {
+                most_significant_bits,
+                array: (0..array.len() / 2).map(|index| u16_at(array, index)).filter(|&value| rhs.contains(value)).collect(),
+            }),
+            (_, ContainerType::ContainerTypeArray(rhs)) => ContainerType::ContainerTypeArray(ArrayContainer {
+                most_significant_bits,
+                array: rhs.array.iter().copied().filter(|&value| self.contains(value)).collect(),
+            }),
+            (Self::Run(_), ContainerType::ContainerTypeRun(rhs)) => {
+                let mut res = RunContainer::new(most_significant_bits);
+                let mut rhs_idx = 0;
+                for (lhs_start, lhs_end) in self.runs() {
+                    while rhs_idx < rhs.runs.len() {
+                        let (rhs_start, rhs_length) = rhs.runs[rhs_idx];
+                        let rhs_end = rhs_start + rhs_length;
+                        let start = lhs_start.max(rhs_start);
+                        let end = lhs_end.min(rhs_end);
+                        if start <= end {
+                            res.push_run(start, end);
+                        }
+                        if lhs_end < rhs_end {
+                            break;
+                        }
+                        rhs_idx += 1;
+                    }
+                }
+                ContainerType::from_run(res)
+            }
+            (_, ContainerType::ContainerTypeBitmap(rhs)) => {
+                let mut bitmap = Box::new([0u64; 1024]);
+                self.for_each_word(|index, word| bitmap[index] |= word & rhs.bitmap[index]);
+                ContainerType::from_bitmap(BitmapContainer::from_words(most_significant_bits, bitmap))
+            }
+            (Self::Bitmap(lhs), rhs) => {
+                let mut bitmap = Box::new([0u64; 1024]);
+                rhs.for_each_word(|index, word| bitmap[index] |= word & u64_at(lhs, index));
+                ContainerType::from_bitmap(BitmapContainer::from_words(most_significant_bits, bitmap))
+            }
+        }
+    }
+
+    // Same as `ContainerType::for_each_word`.
+    fn for_each_word(&self, mut f: impl FnMut(usize, u64)) {
+        match *self {
+            Self::Array(array) => {
+                for index in 0..array.len() / 2 {
+                    let (index, offset) = BitmapContainer::get_index_and_offset(&u16_at(array, index));
+                    f(index, 1 << offset);
+                }
+            }
+            Self::Bitmap(bitmap) => {
+                for index in 0..bitmap.len() / 8 {
+                    let word = u64_at(bitmap, index);
+                    if word != 0 {
+                        f(index, word);
+                    }
+                }
+            }
+            Self::Run(_) => {
+                for (start, end) in self.runs() {
+                    for (index, mask) in BitmapContainer::range_masks(start, end) {
+                        f(index, mask);
+                    }
+                }
+            }
+        }
+    }
+
+    // The values of an array or run container as inclusive (start, end) ranges.
+    fn runs(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let (array, runs) = match *self {
+            Self::Array(array) => (array, &[][..]),
+            Self::Run(runs) => (&[][..], runs),
+            Self::Bitmap(_) => unreachable!("bitmap containers are not read as runs"),
+        };
+        let values = (0..array.len() / 2).map(move |index| (u16_at(array, index), u16_at(array, index)));
+        values.chain((0..runs.len() / 4).map(move |index| {
+            let (start, length) = run_at(runs, index);
+            (start, start.saturating_add(length))
+        }))
+    }
+}
+
+// The values of an owned array or run container as inclusive (start, end) ranges.
+fn owned_runs(container: &ContainerType) -> impl Iterator<Item = (u16, u16)> + '_ {
+    let (array, runs): (&[u16], &[(u16, u16)]) = match container {
+        ContainerType::ContainerTypeArray(array_container) => (&array_container.array, &[]),
+        ContainerType::ContainerTypeRun(run_container) => (&[], &run_container.runs),
+        ContainerType::ContainerTypeBitmap(_) => unreachable!("bitmap containers are not read as runs"),
+    };
+    array.iter().map(|&value| (value, value)).chain(runs.iter().map(|&(start, length)| (start, start + length)))
+}
+
+/// An iterator over the values of a [`FrozenRoaringBitmap`], in ascending order.
+pub struct FrozenIter<'a> {
+    containers: std::slice::Iter<'a, FrozenContainer<'a>>,
+    current: Option<(&'a FrozenContainer<'a>, FrozenCursor)>,
+}
+
+impl Iterator for FrozenIter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        loop {
+            if let Some((container, cursor)) = &mut self.current {
+                if let Some(value) = cursor.next(&container.container) {
+                    return Some((container.most_significant_bits as u32) << 16 | value as u32);
+                }
+            }
+            let container = self.containers.next()?;
+            self.current = Some((container, FrozenCursor::new(&container.container)));
+        }
+    }
+}
+
+// Same as `ContainerCursor`, but decoding values from the serialized payloads.
+enum FrozenCursor {
+    Array { index: usize },
+    Bitmap { index: usize, word: u64 },
+    Run { index: usize, value: u32 },
+}
+
+impl FrozenCursor {
+    fn new(container: &FrozenContainerType) -> Self {
+        match *container {
+            FrozenContainerType::Array(_) => Self::Array { index: 0 },
+            FrozenContainerType::Bitmap(bitmap) => Self::Bitmap { index: 0, word: u64_at(bitmap, 0) },
+            FrozenContainerType::Run(runs) => Self::Run {
+                index: 0,
+                value: if runs.is_empty() { 0 } else { run_at(runs, 0).0 as u32 },
+            },
+        }
+    }
+
+    fn next(&mut self, container: &FrozenContainerType) -> Option<u16> {
+        match (self, container) {
+            (Self::Array { index }, &FrozenContainerType::Array(array)) => {
+                if *index == array.len() / 2 {
+                    return None;
+                }
+                *index += 1;
+                Some(u16_at(array, *index - 1))
+            }
+            (Self::Bitmap { index, word }, &FrozenContainerType::Bitmap(bitmap)) => {
+                while *word == 0 {
+                    if *index + 1 == bitmap.len() / 8 {
+                        return None;
+                    }
+                    *index += 1;
+                    *word = u64_at(bitmap, *index);
+                }
+                let offset = word.trailing_zeros() as usize;
+                *word &= *word - 1;
+                Some((*index * 64 + offset) as u16)
+            }
+            (Self::Run { index, value }, &FrozenContainerType::Run(runs)) => {
+                if *index == runs.len() / 4 {
+                    return None;
+                }
+                let (start, length) = run_at(runs, *index);
+                let current = *value;
+                if current >= start as u32 + length as u32 {
+                    *index += 1;
+                    if *index < runs.len() / 4 {
+                        *value = run_at(runs, *index).0 as u32;
+                    }
+                } else {
+                    *value += 1;
+                }
+                Some(current as u16)
+            }
+            _ => unreachable!("cursor does not match its container type"),
+        }
+    }
+}
+
+// Index of the first element in `0..len` for which `predicate` is false, assuming
+// it holds for a prefix of the range.
+fn partition_point(len: usize, predicate: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if predicate(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+fn u16_at(bytes: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([bytes[2 * index], bytes[2 * index + 1]])
+}
+
+fn u64_at(bytes: &[u8], index: usize) -> u64 {
+    u64::from_le_bytes(bytes[8 * index..8 * index + 8].try_into().expect("slice is 8 bytes long"))
+}
+
+fn run_at(runs: &[u8], index: usize) -> (u16, u16) {
+    (u16_at(runs, 2 * index), u16_at(runs, 2 * index + 1))
+}
+
+fn slice(bytes: &[u8], start: usize, len: usize) -> io::Result<&[u8]> {
+    start
+        .checked_add(len)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(bitmap: &RoaringBitmap) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        bytes
+    }
+
+    // Arrays, a bitmap, full and partial run containers, spread over enough
+    // containers for the offset header to be written either way.
+    fn sample(run_optimize: bool) -> RoaringBitmap {
+        let mut bitmap: RoaringBitmap = (0..100_000).step_by(7).collect();
+        for i in (3 * 65536..4 * 65536).step_by(3) {
+            bitmap.add(i);
+        }
+        bitmap.insert_range(5 * 65536..7 * 65536 + 100);
+        bitmap.insert_range(9 * 65536 + 10..9 * 65536 + 20);
+        bitmap.add(u32::MAX);
+        if run_optimize {
+            bitmap.run_optimize();
+        }
+        bitmap
+    }
+
+    #[test]
+    fn queries_match_owned_bitmap() {
+        for run_optimize in [false, true] {
+            let bitmap = sample(run_optimize);
+            let bytes = serialize(&bitmap);
+            let frozen = FrozenRoaringBitmap::from_bytes(&bytes).unwrap();
+            assert_eq!(frozen.len(), bitmap.len());
+            assert!(!frozen.is_empty());
+            assert!(frozen.iter().eq(bitmap.iter()));
+            assert!((&frozen).into_iter().eq(bitmap.iter()));
+            for value in (0..10 * 65536).step_by(11).chain([9 * 65536 + 9, 9 * 65536 + 19, 9 * 65536 + 20, u32::MAX - 1, u32::MAX]) {
+                assert_eq!(frozen.contains(value), bitmap.contains(value), "contains({value})");
+                assert_eq!(frozen.rank(value), bitmap.rank(value), "rank({value})");
+            }
+        }
+    }
+
+    #[test]
+    fn few_run_containers_without_offsets() {
+        let mut bitmap: RoaringBitmap = (0..10).chain([2 * 65536 + 5]).collect();
+        bitmap.run_optimize();
+        let bytes = serialize(&bitmap);
+        let frozen = FrozenRoaringBitmap::from_bytes(&bytes).unwrap();
+        assert!(frozen.iter().eq(bitmap.iter()));
+        assert!(frozen.contains(9) && !frozen.contains(10) && frozen.contains(2 * 65536 + 5));
+        assert_eq!(frozen.rank(2 * 65536 + 5), 11);
+    }
+
+    #[test]
+    fn empty_bitmap() {
+        let bytes = serialize(&RoaringBitmap::new());
+        let frozen = FrozenRoaringBitmap::from_bytes(&bytes).unwrap();
+        assert!(frozen.is_empty());
+        assert_eq!(frozen.len(), 0);
+        assert_eq!(frozen.iter().next(), None);
+        assert!(!frozen.contains(0));
+        assert_eq!(frozen.rank(u32::MAX), 0);
+    }
+
+    #[test]
+    fn set_operations_match_owned_bitmap() {
+        let lhs = sample(true);
+        let mut rhs: RoaringBitmap = (0..8 * 65536).step_by(5).collect();
+        rhs.insert_range(20 * 65536..20 * 65536 + 10);
+        let bytes = serialize(&lhs);
+        let frozen = FrozenRoaringBitmap::from_bytes(&bytes).unwrap();
+
+        let union = frozen.union(&rhs);
+        assert!(union.iter().eq(lhs.union(&rhs).iter()));
+        assert_eq!(union.len(), lhs.union(&rhs).len());
+        let intersection = frozen.intersection(&rhs);
+        assert!(intersection.iter().eq(lhs.intersection(&rhs).iter()));
+        assert_eq!(intersection.len(), lhs.intersection(&rhs).len());
+        assert!(frozen.intersection(&RoaringBitmap::new()).is_empty());
+        assert!(frozen.union(&RoaringBitmap::new()).iter().eq(lhs.iter()));
+    }
+
+    #[test]
+    fn set_operations_cover_every_container_pair() {
+        let mut kinds: Vec<RoaringBitmap> = vec![
+            (0..65536).step_by(23).collect(),
+            (3..2000).step_by(2).collect(),
+            (0..65536).filter(|value| value % 5 != 0).collect(),
+            (0..3000).chain(5000..9000).chain(60_000..65536).collect(),
+            (2500..5500).chain([40_000]).collect(),
+        ];
+        let mut full = RoaringBitmap::new();
+        full.insert_range(0..65536);
+        kinds.push(full);
+        for bitmap in &mut kinds {
+            bitmap.run_optimize();
+        }
+        for lhs in &kinds {
+            let bytes = serialize(lhs);
+            let frozen = FrozenRoaringBitmap::from_bytes(&bytes).unwrap();
+            for rhs in &kinds {
+                let union = frozen.union(rhs);
+                assert_eq!(union.validate(), Ok(()));
+                assert!(union.iter().eq(lhs.union(rhs).iter()));
+                let intersection = frozen.intersection(rhs);
+                assert_eq!(intersection.validate(), Ok(()));
+                assert!(intersection.iter().eq(lhs.intersection(rhs).iter()));
+            }
+        }
+    }
+
+    #[test]
+    fn set_operations_recount_corrupt_cardinalities() {
+        let mut bitmap: RoaringBitmap = (0..65536).step_by(2).collect();
+        bitmap.insert_range(65536..65636);
+        bitmap.run_optimize();
+        let mut bytes = serialize(&bitmap);
+        // Cookie and one byte of run flags, then the key and cardinality minus one of
+        // the bitmap container and of the run container.
+        assert_eq!(&bytes[7..9], &(32767u16).to_le_bytes());
+        assert_eq!(&bytes[11..13], &(99u16).to_le_bytes());
+        bytes[7..9].copy_from_slice(&(40_000u16).to_le_bytes());
+        bytes[11..13].copy_from_slice(&(10u16).to_le_bytes());
+        let frozen = FrozenRoaringBitmap::from_bytes(&bytes).unwrap();
+        assert_eq!(frozen.len(), 40_001 + 11);
+
+        let mut full = RoaringBitmap::new();
+        full.insert_range(0..2 * 65536);
+        for res in [frozen.union(&RoaringBitmap::new()), frozen.intersection(&full)] {
+            assert_eq!(res.validate(), Ok(()));
+            assert_eq!(res.len(), bitmap.len());
+            assert_eq!(res.rank(65635), bitmap.len());
+            assert!(res.iter().eq(bitmap.iter()));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bytes = serialize(&sample(true));
+        assert!(FrozenRoaringBitmap::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(FrozenRoaringBitmap::from_bytes(&bytes[..3]).is_err());
+        assert!(FrozenRoaringBitmap::from_bytes(&[0u8; 8]).is_err());
+    }
+}
@@ -1,35 +1,241 @@
-use std::cmp::{max, max_by_key, min_by_key};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Bound, RangeBounds, Sub, SubAssign};
 
+mod frozen;
+mod serialization;
+mod simd;
+mod sorted;
+mod treemap;
+mod validate;
+
+pub use frozen::{FrozenIter, FrozenRoaringBitmap};
+pub use sorted::NonSortedError;
+pub use treemap::{RoaringTreemap, TreemapIter};
+pub use validate::InvariantViolation;
+
+/// A compressed set of `u32` values, split into 2^16 chunks keyed by the
+/// 16 most significant bits of each value.
 #[derive(Debug)]
-struct RoaringBitmap {
+pub struct RoaringBitmap {
     data: Vec<Container>,
 }
 
 impl RoaringBitmap {
-    fn new() -> RoaringBitmap {
+    /// Creates an empty bitmap.
+    pub fn new() -> RoaringBitmap {
         RoaringBitmap {
             data: Vec::new(),
         }
     }
 
-    fn add(&mut self, value: u32) {
+    /// Creates an empty bitmap with room for `capacity` containers, i.e.
+    /// `capacity` distinct values of the 16 most significant bits.
+    pub fn with_capacity(capacity: usize) -> RoaringBitmap {
+        RoaringBitmap {
+            data: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Inserts `value` into the bitmap.
+    pub fn add(&mut self, value: u32) {
         let most_significant_bits = (value >> 16) as u16;
         let least_significant_bits = value as u16;
-        match self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
+        let index = match self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
             Ok(index) => {
-                let mut container = std::mem::replace(&mut self.data[index].container, Default::default());
+                let mut container = std::mem::take(&mut self.data[index].container);
                 container = container.add(least_significant_bits);
                 self.data[index].container = container;
+                index
             }
             Err(index) => {
-                let mut container: ContainerType = Default::default();
+                let mut container = ContainerType::ContainerTypeArray(ArrayContainer::new(most_significant_bits));
                 container = container.add(least_significant_bits);
                 self.data.insert(index, Container { most_significant_bits, container });
+                index
+            }
+        };
+        self.debug_validate(index..index + 1);
+    }
+
+    /// Adds every value of `values`, which may be unsorted and contain repeats. The
+    /// values are sorted and grouped by container, so each container is merged with
+    /// its new values once. Returns the number of values that were not already in
+    /// the bitmap.
+    pub fn add_many(&mut self, values: &[u32]) -> u64 {
+        let mut values = values.to_vec();
+        values.sort_unstable();
+        values.dedup();
+        let mut res = 0;
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut containers = std::mem::take(&mut self.data).into_iter().peekable();
+        for group in values.chunk_by(|lhs, rhs| lhs >> 16 == rhs >> 16) {
+            let most_significant_bits = (group[0] >> 16) as u16;
+            while let Some(container) = containers.next_if(|container| container.most_significant_bits < most_significant_bits) {
+                data.push(container);
+            }
+            let added = ContainerType::from_sorted_array(most_significant_bits, group.iter().map(|&value| value as u16).collect());
+            let container = match containers.next_if(|container| container.most_significant_bits == most_significant_bits) {
+                Some(container) => {
+                    let cardinality = container.container.cardinality();
+                    let container = container.container.union_with(&added);
+                    res += (container.cardinality() - cardinality) as u64;
+                    container
+                }
+                None => {
+                    res += added.cardinality() as u64;
+                    added
+                }
+            };
+            data.push(Container { most_significant_bits, container });
+        }
+        data.extend(containers);
+        self.data = data;
+        self.debug_validate_keys(values.chunk_by(|lhs, rhs| lhs >> 16 == rhs >> 16).map(|group| (group[0] >> 16) as u16));
+        res
+    }
+
+    /// Inserts every value in `range`, a container at a time. Returns the number of
+    /// values that were not already in the bitmap.
+    pub fn insert_range<R: RangeBounds<u32>>(&mut self, range: R) -> u64 {
+        let Some((start, end)) = Self::inclusive_bounds(range) else {
+            return 0;
+        };
+        let start_most_significant_bits = (start >> 16) as u16;
+        let end_most_significant_bits = (end >> 16) as u16;
+        let lo = self.data.partition_point(|container| container.most_significant_bits < start_most_significant_bits);
+        let hi = self.data.partition_point(|container| container.most_significant_bits <= end_most_significant_bits);
+        let mut existing = self.data.drain(lo..hi).collect::<Vec<_>>().into_iter().peekable();
+
+        let mut inserted = 0;
+        let mut containers = Vec::with_capacity((end_most_significant_bits - start_most_significant_bits) as usize + 1);
+        for most_significant_bits in start_most_significant_bits..=end_most_significant_bits {
+            let (range_start, range_end) = Self::chunk_bounds(most_significant_bits, start, end);
+            let container = match existing.next_if(|container| container.most_significant_bits == most_significant_bits) {
+                Some(container) => {
+                    let cardinality = container.container.cardinality();
+                    let container = container.container.add_range(most_significant_bits, range_start, range_end);
+                    inserted += (container.cardinality() - cardinality) as u64;
+                    container
+                }
+                None => {
+                    let container = ContainerType::from_range(most_significant_bits, range_start, range_end);
+                    inserted += container.cardinality() as u64;
+                    container
+                }
+            };
+            containers.push(Container { most_significant_bits, container });
+        }
+        let inserted_containers = containers.len();
+        self.data.splice(lo..lo, containers);
+        self.debug_validate(lo..lo + inserted_containers);
+        inserted
+    }
+
+    /// Removes every value in `range`, dropping the containers it covers entirely.
+    /// Returns the number of values that were removed.
+    pub fn remove_range<R: RangeBounds<u32>>(&mut self, range: R) -> u64 {
+        let Some((start, end)) = Self::inclusive_bounds(range) else {
+            return 0;
+        };
+        let start_most_significant_bits = (start >> 16) as u16;
+        let end_most_significant_bits = (end >> 16) as u16;
+        let lo = self.data.partition_point(|container| container.most_significant_bits < start_most_significant_bits);
+        let hi = self.data.partition_point(|container| container.most_significant_bits <= end_most_significant_bits);
+        let existing: Vec<Container> = self.data.drain(lo..hi).collect();
+
+        let mut removed = 0;
+        let mut containers = Vec::new();
+        for mut container in existing {
+            let (range_start, range_end) = Self::chunk_bounds(container.most_significant_bits, start, end);
+            let cardinality = container.container.cardinality();
+            let remaining = std::mem::take(&mut container.container).remove_range(range_start, range_end);
+            removed += (cardinality - remaining.cardinality()) as u64;
+            if !remaining.is_empty() {
+                container.container = remaining;
+                containers.push(container);
+            }
+        }
+        let remaining_containers = containers.len();
+        self.data.splice(lo..lo, containers);
+        self.debug_validate(lo..lo + remaining_containers);
+        removed
+    }
+
+    /// Toggles every value in `range`: values in the bitmap are removed and missing
+    /// ones are inserted.
+    pub fn flip_range<R: RangeBounds<u32>>(&mut self, range: R) {
+        let Some((start, end)) = Self::inclusive_bounds(range) else {
+            return;
+        };
+        let start_most_significant_bits = (start >> 16) as u16;
+        let end_most_significant_bits = (end >> 16) as u16;
+        let lo = self.data.partition_point(|container| container.most_significant_bits < start_most_significant_bits);
+        let hi = self.data.partition_point(|container| container.most_significant_bits <= end_most_significant_bits);
+        let mut existing = self.data.drain(lo..hi).collect::<Vec<_>>().into_iter().peekable();
+
+        let mut containers = Vec::with_capacity((end_most_significant_bits - start_most_significant_bits) as usize + 1);
+        for most_significant_bits in start_most_significant_bits..=end_most_significant_bits {
+            let (range_start, range_end) = Self::chunk_bounds(most_significant_bits, start, end);
+            let container = match existing.next_if(|container| container.most_significant_bits == most_significant_bits) {
+                Some(container) => container.container.flip_range(most_significant_bits, range_start, range_end),
+                None => ContainerType::from_range(most_significant_bits, range_start, range_end),
+            };
+            if !container.is_empty() {
+                containers.push(Container { most_significant_bits, container });
+            }
+        }
+        let flipped_containers = containers.len();
+        self.data.splice(lo..lo, containers);
+        self.debug_validate(lo..lo + flipped_containers);
+    }
+
+    /// Returns the values in `range` that are not in the bitmap.
+    pub fn complement_within<R: RangeBounds<u32>>(&self, range: R) -> Self {
+        let mut res = RoaringBitmap::new();
+        let Some((start, end)) = Self::inclusive_bounds(range) else {
+            return res;
+        };
+        let start_most_significant_bits = (start >> 16) as u16;
+        let end_most_significant_bits = (end >> 16) as u16;
+        let mut idx = self.data.partition_point(|container| container.most_significant_bits < start_most_significant_bits);
+        for most_significant_bits in start_most_significant_bits..=end_most_significant_bits {
+            let (range_start, range_end) = Self::chunk_bounds(most_significant_bits, start, end);
+            let mut container = ContainerType::from_range(most_significant_bits, range_start, range_end);
+            if idx < self.data.len() && self.data[idx].most_significant_bits == most_significant_bits {
+                container = container.difference_with(&self.data[idx].container);
+                idx += 1;
+            }
+            if !container.is_empty() {
+                res.data.push(Container { most_significant_bits, container });
             }
+        }
+        res
+    }
+
+    fn inclusive_bounds<R: RangeBounds<u32>>(range: R) -> Option<(u32, u32)> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
         };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end,
+            Bound::Excluded(&end) => end.checked_sub(1)?,
+            Bound::Unbounded => u32::MAX,
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    // The part of start..=end that falls in the container for `most_significant_bits`.
+    fn chunk_bounds(most_significant_bits: u16, start: u32, end: u32) -> (u16, u16) {
+        let range_start = if (start >> 16) as u16 == most_significant_bits { start as u16 } else { 0 };
+        let range_end = if (end >> 16) as u16 == most_significant_bits { end as u16 } else { u16::MAX };
+        (range_start, range_end)
     }
 
-    fn contains(&self, value: u32) -> bool {
+    /// Returns `true` if `value` is in the bitmap.
+    pub fn contains(&self, value: u32) -> bool {
         let most_significant_bits = (value >> 16) as u16;
         let least_significant_bits = value as u16;
         match self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
@@ -38,17 +244,25 @@ impl RoaringBitmap {
         }
     }
 
-    fn remove(&mut self, value: u32) {
+    /// Removes `value` from the bitmap, if present.
+    pub fn remove(&mut self, value: u32) {
         let most_significant_bits = (value >> 16) as u16;
         if let Ok(index) = self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
             let least_significant_bits = value as u16;
-            let mut container = std::mem::replace(&mut self.data[index].container, Default::default());
+            let mut container = std::mem::take(&mut self.data[index].container);
             container = container.remove(least_significant_bits);
-            self.data[index].container = container;
+            if container.is_empty() {
+                self.data.remove(index);
+                self.debug_validate(index..index);
+            } else {
+                self.data[index].container = container;
+                self.debug_validate(index..index + 1);
+            }
         }
     }
 
-    fn union(&self, rhs: &RoaringBitmap) -> Self {
+    /// Returns the values that are in `self`, `rhs` or both.
+    pub fn union(&self, rhs: &RoaringBitmap) -> Self {
         let mut res = RoaringBitmap::new();
         let mut lhs_idx = 0;
         let mut rhs_idx = 0;
@@ -81,7 +295,8 @@ impl RoaringBitmap {
         res
     }
 
-    fn intersection(&self, rhs: &RoaringBitmap) -> Self {
+    /// Returns the values that are in both `self` and `rhs`.
+    pub fn intersection(&self, rhs: &RoaringBitmap) -> Self {
         let mut res = RoaringBitmap::new();
         let mut lhs_idx = 0;
         let mut rhs_idx = 0;
@@ -107,488 +322,2926 @@ impl RoaringBitmap {
         res
     }
 
-    fn is_empty(&self) -> bool {
-        self.data.is_empty()
-    }
-}
-
-#[derive(Debug)]
-struct Container {
-    most_significant_bits: u16,
-    container: ContainerType,
-}
-
-impl Clone for Container {
-    fn clone(&self) -> Self {
-        Container {
-            most_significant_bits: self.most_significant_bits,
-            container: self.container.clone(),
+    /// Returns the values that are in `self` but not in `rhs`.
+    pub fn difference(&self, rhs: &RoaringBitmap) -> Self {
+        let mut res = RoaringBitmap::new();
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < self.data.len() && rhs_idx < rhs.data.len() {
+            let lhs_container = &self.data[lhs_idx];
+            let rhs_container = &rhs.data[rhs_idx];
+            if lhs_container.most_significant_bits == rhs_container.most_significant_bits {
+                let container = lhs_container.container.difference(&rhs_container.container);
+                if !container.is_empty() {
+                    res.data.push(Container {
+                        most_significant_bits: lhs_container.most_significant_bits,
+                        container,
+                    });
+                }
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_container.most_significant_bits < rhs_container.most_significant_bits {
+                res.data.push(lhs_container.clone());
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        while lhs_idx < self.data.len() {
+            res.data.push(self.data[lhs_idx].clone());
+            lhs_idx += 1;
         }
+        res
     }
-}
-
-#[derive(Debug)]
-enum ContainerType {
-    ContainerTypeArray(ArrayContainer),
-    ContainerTypeBitmap(BitmapContainer),
-}
-
-impl Default for ContainerType {
-    fn default() -> Self { ContainerType::ContainerTypeArray(ArrayContainer::new(0)) }
-}
 
-impl ContainerType {
-    fn add(self, value: u16) -> Self {
-        match self {
-            Self::ContainerTypeArray(mut array_container) => {
-                // This check ensures that duplicate values do not cause unnecessary
-                // container conversion, because duplicate values will not increase the
-                // container size beyond 4096.
-                if array_container.array.len() == 4096 && !array_container.contains(&value) {
-                    let mut bitmap_container = BitmapContainer::from(array_container);
-                    bitmap_container.add(value);
-                    Self::ContainerTypeBitmap(bitmap_container)
-                } else {
-                    array_container.add(value);
-                    Self::ContainerTypeArray(array_container)
+    /// Returns the values that are in exactly one of `self` and `rhs`.
+    pub fn symmetric_difference(&self, rhs: &RoaringBitmap) -> Self {
+        let mut res = RoaringBitmap::new();
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < self.data.len() && rhs_idx < rhs.data.len() {
+            let lhs_container = &self.data[lhs_idx];
+            let rhs_container = &rhs.data[rhs_idx];
+            if lhs_container.most_significant_bits == rhs_container.most_significant_bits {
+                let container = lhs_container.container.symmetric_difference(&rhs_container.container);
+                if !container.is_empty() {
+                    res.data.push(Container {
+                        most_significant_bits: lhs_container.most_significant_bits,
+                        container,
+                    });
                 }
-            }
-            Self::ContainerTypeBitmap(mut bitmap_container) => {
-                bitmap_container.add(value);
-                Self::ContainerTypeBitmap(bitmap_container)
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_container.most_significant_bits < rhs_container.most_significant_bits {
+                res.data.push(lhs_container.clone());
+                lhs_idx += 1;
+            } else {
+                res.data.push(rhs_container.clone());
+                rhs_idx += 1;
             }
         }
-    }
-
-    fn contains(&self, value: u16) -> bool {
-        match self {
-            Self::ContainerTypeArray(array_container) => array_container.contains(&value),
-            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.contains(&value),
+        while lhs_idx < self.data.len() {
+            res.data.push(self.data[lhs_idx].clone());
+            lhs_idx += 1;
+        }
+        while rhs_idx < rhs.data.len() {
+            res.data.push(rhs.data[rhs_idx].clone());
+            rhs_idx += 1;
         }
+        res
     }
 
-    fn remove(self, value: u16) -> Self {
-        match self {
-            Self::ContainerTypeArray(mut array_container) => {
-                array_container.remove(&value);
-                Self::ContainerTypeArray(array_container)
+    /// Adds every value of `rhs` to `self`, reusing the containers of `self`.
+    pub fn union_with(&mut self, rhs: &RoaringBitmap) {
+        let mut data = Vec::with_capacity(self.data.len() + rhs.data.len());
+        let mut rhs_idx = 0;
+        for mut lhs_container in std::mem::take(&mut self.data) {
+            while rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits < lhs_container.most_significant_bits {
+                data.push(rhs.data[rhs_idx].clone());
+                rhs_idx += 1;
             }
-            Self::ContainerTypeBitmap(mut bitmap_container) => {
-                bitmap_container.remove(&value);
-                if bitmap_container.cardinality == 4096 {
-                    let array_container = ArrayContainer::from(bitmap_container);
-                    Self::ContainerTypeArray(array_container)
-                } else {
-                    Self::ContainerTypeBitmap(bitmap_container)
-                }
+            if rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits == lhs_container.most_significant_bits {
+                lhs_container.container = lhs_container.container.union_with(&rhs.data[rhs_idx].container);
+                rhs_idx += 1;
             }
+            data.push(lhs_container);
         }
+        data.extend_from_slice(&rhs.data[rhs_idx..]);
+        self.data = data;
+        self.debug_validate_keys(rhs.data.iter().map(|container| container.most_significant_bits));
     }
 
-    fn union(&self, rhs: &Self) -> Self {
-        match (self, rhs) {
-            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
-                Self::union_array_array(lhs, rhs)
+    /// Keeps only the values of `self` that are also in `rhs`.
+    pub fn intersect_with(&mut self, rhs: &RoaringBitmap) {
+        let mut rhs_idx = 0;
+        self.data.retain_mut(|lhs_container| {
+            while rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits < lhs_container.most_significant_bits {
+                rhs_idx += 1;
             }
-            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
-                Self::union_bitmap_bitmap(lhs, rhs)
+            if rhs_idx == rhs.data.len() || rhs.data[rhs_idx].most_significant_bits != lhs_container.most_significant_bits {
+                return false;
             }
-            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
-                Self::union_array_bitmap(lhs, rhs)
+            let container = std::mem::take(&mut lhs_container.container);
+            lhs_container.container = container.intersect_with(&rhs.data[rhs_idx].container);
+            !lhs_container.container.is_empty()
+        });
+        self.debug_validate_keys(rhs.data.iter().map(|container| container.most_significant_bits));
+    }
+
+    /// Removes every value of `rhs` from `self`.
+    pub fn difference_with(&mut self, rhs: &RoaringBitmap) {
+        let mut rhs_idx = 0;
+        self.data.retain_mut(|lhs_container| {
+            while rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits < lhs_container.most_significant_bits {
+                rhs_idx += 1;
             }
-            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
-                Self::union_array_bitmap(rhs, lhs)
+            if rhs_idx == rhs.data.len() || rhs.data[rhs_idx].most_significant_bits != lhs_container.most_significant_bits {
+                return true;
             }
-        }
+            let container = std::mem::take(&mut lhs_container.container);
+            lhs_container.container = container.difference_with(&rhs.data[rhs_idx].container);
+            !lhs_container.container.is_empty()
+        });
+        self.debug_validate_keys(rhs.data.iter().map(|container| container.most_significant_bits));
     }
 
-    fn intersection(&self, rhs: &Self) -> Self {
-        match (self, rhs) {
-            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
-                Self::intersection_array_array(lhs, rhs)
-            }
-            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
-                Self::intersection_bitmap_bitmap(lhs, rhs)
+    /// Keeps the values that are in exactly one of `self` and `rhs`.
+    pub fn symmetric_difference_with(&mut self, rhs: &RoaringBitmap) {
+        let mut data = Vec::with_capacity(self.data.len() + rhs.data.len());
+        let mut rhs_idx = 0;
+        for mut lhs_container in std::mem::take(&mut self.data) {
+            while rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits < lhs_container.most_significant_bits {
+                data.push(rhs.data[rhs_idx].clone());
+                rhs_idx += 1;
             }
-            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
-                Self::intersection_array_bitmap(lhs, rhs)
+            if rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits == lhs_container.most_significant_bits {
+                lhs_container.container = lhs_container.container.symmetric_difference_with(&rhs.data[rhs_idx].container);
+                rhs_idx += 1;
             }
-            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
-                Self::intersection_array_bitmap(rhs, lhs)
+            if !lhs_container.container.is_empty() {
+                data.push(lhs_container);
             }
         }
+        data.extend_from_slice(&rhs.data[rhs_idx..]);
+        self.data = data;
+        self.debug_validate_keys(rhs.data.iter().map(|container| container.most_significant_bits));
     }
 
-    fn is_empty(&self) -> bool {
-        match self {
-            Self::ContainerTypeArray(array_container) => array_container.array.is_empty(),
-            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.cardinality == 0,
-        }
+    /// Returns the values that are in any of `bitmaps`. Containers with the same key
+    /// are merged together in a single pass, instead of one pairwise union per bitmap.
+    pub fn union_many<'a, I: IntoIterator<Item = &'a RoaringBitmap>>(bitmaps: I) -> Self {
+        let bitmaps: Vec<&RoaringBitmap> = bitmaps.into_iter().collect();
+        let mut res = RoaringBitmap::new();
+        Self::for_each_key_group(&bitmaps, |most_significant_bits, group| {
+            res.data.push(Container {
+                most_significant_bits,
+                container: ContainerType::union_many(most_significant_bits, group),
+            });
+        });
+        res.debug_validate(0..res.data.len());
+        res
     }
-}
 
-impl Clone for ContainerType {
-    fn clone(&self) -> Self {
-        match self {
-            Self::ContainerTypeArray(array_container) => Self::ContainerTypeArray(array_container.clone()),
-            Self::ContainerTypeBitmap(bitmap_container) => Self::ContainerTypeBitmap(bitmap_container.clone()),
+    // Calls `f` once per key present in any of `bitmaps`, in ascending key order,
+    // with the containers stored under that key. A heap holds the next container of
+    // every bitmap, keyed by its most significant bits.
+    fn for_each_key_group<'a>(bitmaps: &[&'a RoaringBitmap], mut f: impl FnMut(u16, &[&'a Container])) {
+        let mut heap: BinaryHeap<Reverse<(u16, usize, usize)>> = bitmaps
+            .iter()
+            .enumerate()
+            .filter(|(_, bitmap)| !bitmap.data.is_empty())
+            .map(|(bitmap_idx, bitmap)| Reverse((bitmap.data[0].most_significant_bits, bitmap_idx, 0)))
+            .collect();
+        let mut group: Vec<&Container> = Vec::new();
+        while let Some(&Reverse((most_significant_bits, _, _))) = heap.peek() {
+            while let Some(&Reverse((next_most_significant_bits, bitmap_idx, container_idx))) = heap.peek() {
+                if next_most_significant_bits != most_significant_bits {
+                    break;
+                }
+                heap.pop();
+                let bitmap = bitmaps[bitmap_idx];
+                group.push(&bitmap.data[container_idx]);
+                if container_idx + 1 < bitmap.data.len() {
+                    heap.push(Reverse((bitmap.data[container_idx + 1].most_significant_bits, bitmap_idx, container_idx + 1)));
+                }
+            }
+            f(most_significant_bits, &group);
+            group.clear();
         }
     }
-}
-
-#[derive(Debug)]
-struct ArrayContainer {
-    most_significant_bits: u16,
-    array: Vec<u16>,
-}
 
-impl ArrayContainer {
-    fn new(most_significant_bits: u16) -> Self {
-        ArrayContainer {
-            most_significant_bits,
-            array: Vec::new(),
+    /// Returns the values that are in every one of `bitmaps`, or an empty bitmap if
+    /// there are none. Starts from the smallest bitmap and stops as soon as the
+    /// result is empty.
+    pub fn intersection_many<'a, I: IntoIterator<Item = &'a RoaringBitmap>>(bitmaps: I) -> Self {
+        let mut bitmaps: Vec<&RoaringBitmap> = bitmaps.into_iter().collect();
+        bitmaps.sort_by_cached_key(|bitmap| bitmap.len());
+        let Some((smallest, rest)) = bitmaps.split_first() else {
+            return RoaringBitmap::new();
+        };
+        let mut res = (*smallest).clone();
+        for bitmap in rest {
+            if res.is_empty() {
+                break;
+            }
+            res.intersect_with(bitmap);
         }
-    }
-    fn add(&mut self, value: u16) {
-        self.array.binary_search(&value).err().map(|index| self.array.insert(index, value));
+        res
     }
 
-    fn contains(&self, value: &u16) -> bool {
-        self.array.binary_search(value).is_ok()
+    /// Returns the values that are in at least `k` of `bitmaps`. Every value is in at
+    /// least zero of them, so a `k` of 0 returns the full range.
+    pub fn at_least<'a, I: IntoIterator<Item = &'a RoaringBitmap>>(bitmaps: I, k: usize) -> Self {
+        let bitmaps: Vec<&RoaringBitmap> = bitmaps.into_iter().collect();
+        if k == 0 {
+            let mut res = RoaringBitmap::new();
+            res.insert_range(..);
+            return res;
+        }
+        if k == 1 {
+            return Self::union_many(bitmaps);
+        }
+        if k == bitmaps.len() {
+            return Self::intersection_many(bitmaps);
+        }
+        let mut res = RoaringBitmap::new();
+        if k > bitmaps.len() {
+            return res;
+        }
+        Self::for_each_key_group(&bitmaps, |most_significant_bits, group| {
+            if group.len() < k {
+                return;
+            }
+            let container = ContainerType::at_least(most_significant_bits, group, k);
+            if !container.is_empty() {
+                res.data.push(Container { most_significant_bits, container });
+            }
+        });
+        res.debug_validate(0..res.data.len());
+        res
     }
 
-    fn remove(&mut self, value: &u16) {
-        self.array.binary_search(value).ok().map(|index| self.array.remove(index));
+    /// Returns the values that are in more than half of `bitmaps`.
+    pub fn majority<'a, I: IntoIterator<Item = &'a RoaringBitmap>>(bitmaps: I) -> Self {
+        let bitmaps: Vec<&RoaringBitmap> = bitmaps.into_iter().collect();
+        let k = bitmaps.len() / 2 + 1;
+        Self::at_least(bitmaps, k)
     }
-}
 
-impl From<BitmapContainer> for ArrayContainer {
-    fn from(bitmap_container: BitmapContainer) -> Self {
-        let mut array = Vec::with_capacity(bitmap_container.cardinality as usize);
-        for (index, bitmap) in bitmap_container.bitmap.iter().enumerate() {
-            if *bitmap == 0 {
+    /// Returns the number of values in both `self` and `rhs`, without building the
+    /// intersection.
+    pub fn intersection_len(&self, rhs: &RoaringBitmap) -> u64 {
+        let mut res = 0;
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < self.data.len() && rhs_idx < rhs.data.len() {
+            let lhs_container = &self.data[lhs_idx];
+            let rhs_container = &rhs.data[rhs_idx];
+            if lhs_container.most_significant_bits == rhs_container.most_significant_bits {
+                res += lhs_container.container.intersection_len(&rhs_container.container) as u64;
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_container.most_significant_bits < rhs_container.most_significant_bits {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        res
+    }
+
+    /// Returns the number of values in `self`, `rhs` or both, without building the
+    /// union.
+    pub fn union_len(&self, rhs: &RoaringBitmap) -> u64 {
+        self.len() + rhs.len() - self.intersection_len(rhs)
+    }
+
+    /// Returns the number of values in `self` but not in `rhs`, without building the
+    /// difference.
+    pub fn difference_len(&self, rhs: &RoaringBitmap) -> u64 {
+        self.len() - self.intersection_len(rhs)
+    }
+
+    /// Returns the number of values in exactly one of `self` and `rhs`, without
+    /// building the symmetric difference.
+    pub fn symmetric_difference_len(&self, rhs: &RoaringBitmap) -> u64 {
+        self.len() + rhs.len() - 2 * self.intersection_len(rhs)
+    }
+
+    /// Returns the size of the intersection of `self` and `rhs` divided by the size of
+    /// their union. The result is NaN when both bitmaps are empty.
+    pub fn jaccard_index(&self, rhs: &RoaringBitmap) -> f64 {
+        let intersection_len = self.intersection_len(rhs);
+        intersection_len as f64 / (self.len() + rhs.len() - intersection_len) as f64
+    }
+
+    /// Returns `true` if every value of `self` is also in `rhs`. Stops at the first
+    /// value that is not.
+    pub fn is_subset(&self, rhs: &RoaringBitmap) -> bool {
+        if self.data.len() > rhs.data.len() {
+            return false;
+        }
+        let mut rhs_idx = 0;
+        for lhs_container in &self.data {
+            while rhs_idx < rhs.data.len() && rhs.data[rhs_idx].most_significant_bits < lhs_container.most_significant_bits {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.data.len() || rhs.data[rhs_idx].most_significant_bits != lhs_container.most_significant_bits {
+                return false;
+            }
+            if !lhs_container.container.is_subset(&rhs.data[rhs_idx].container) {
+                return false;
+            }
+            rhs_idx += 1;
+        }
+        true
+    }
+
+    /// Returns `true` if every value of `rhs` is also in `self`.
+    pub fn is_superset(&self, rhs: &RoaringBitmap) -> bool {
+        rhs.is_subset(self)
+    }
+
+    /// Returns `true` if `self` and `rhs` have no value in common. Stops at the first
+    /// common value.
+    pub fn is_disjoint(&self, rhs: &RoaringBitmap) -> bool {
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < self.data.len() && rhs_idx < rhs.data.len() {
+            let lhs_container = &self.data[lhs_idx];
+            let rhs_container = &rhs.data[rhs_idx];
+            if lhs_container.most_significant_bits == rhs_container.most_significant_bits {
+                if !lhs_container.container.is_disjoint(&rhs_container.container) {
+                    return false;
+                }
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_container.most_significant_bits < rhs_container.most_significant_bits {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        true
+    }
+
+    /// Returns an iterator over the values of the bitmap in ascending order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            containers: self.data.iter(),
+            current: None,
+        }
+    }
+
+    /// Returns `true` if the bitmap holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the number of values in the bitmap. Every container keeps its own
+    /// count up to date, so this only walks the containers.
+    pub fn len(&self) -> u64 {
+        self.data.iter().map(|container| container.container.cardinality() as u64).sum()
+    }
+
+    /// Returns the number of values in the bitmap that are less than or equal to `value`.
+    pub fn rank(&self, value: u32) -> u64 {
+        let most_significant_bits = (value >> 16) as u16;
+        let least_significant_bits = value as u16;
+        let (preceding, rank_in_container) =
+            match self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
+                Ok(index) => (index, self.data[index].container.rank(least_significant_bits) as u64),
+                Err(index) => (index, 0),
+            };
+        let preceding_len: u64 = self.data[..preceding].iter().map(|container| container.container.cardinality() as u64).sum();
+        preceding_len + rank_in_container
+    }
+
+    /// Returns the `n`-th smallest value in the bitmap, counting from zero, or `None`
+    /// if the bitmap holds `n` values or fewer.
+    pub fn select(&self, n: u64) -> Option<u32> {
+        let mut remaining = n;
+        for container in &self.data {
+            let cardinality = container.container.cardinality() as u64;
+            if remaining < cardinality {
+                let least_significant_bits = container.container.select(remaining as usize);
+                return Some((container.most_significant_bits as u32) << 16 | least_significant_bits as u32);
+            }
+            remaining -= cardinality;
+        }
+        None
+    }
+
+    /// Returns the smallest value in the bitmap.
+    pub fn min(&self) -> Option<u32> {
+        self.data.iter().find_map(|container| {
+            let least_significant_bits = container.container.min()?;
+            Some((container.most_significant_bits as u32) << 16 | least_significant_bits as u32)
+        })
+    }
+
+    /// Returns the largest value in the bitmap.
+    pub fn max(&self) -> Option<u32> {
+        self.data.iter().rev().find_map(|container| {
+            let least_significant_bits = container.container.max()?;
+            Some((container.most_significant_bits as u32) << 16 | least_significant_bits as u32)
+        })
+    }
+
+    /// Returns the smallest value in the bitmap that is strictly greater than `value`.
+    pub fn successor(&self, value: u32) -> Option<u32> {
+        let value = value.checked_add(1)?;
+        let most_significant_bits = (value >> 16) as u16;
+        let least_significant_bits = value as u16;
+        let index = match self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
+            Ok(index) => {
+                if let Some(next) = self.data[index].container.next_from(least_significant_bits) {
+                    return Some((most_significant_bits as u32) << 16 | next as u32);
+                }
+                index + 1
+            }
+            Err(index) => index,
+        };
+        self.data[index..].iter().find_map(|container| {
+            let least_significant_bits = container.container.min()?;
+            Some((container.most_significant_bits as u32) << 16 | least_significant_bits as u32)
+        })
+    }
+
+    /// Returns the largest value in the bitmap that is strictly less than `value`.
+    pub fn predecessor(&self, value: u32) -> Option<u32> {
+        let value = value.checked_sub(1)?;
+        let most_significant_bits = (value >> 16) as u16;
+        let least_significant_bits = value as u16;
+        let index = match self.data.binary_search_by_key(&most_significant_bits, |container| container.most_significant_bits) {
+            Ok(index) => {
+                if let Some(previous) = self.data[index].container.previous_from(least_significant_bits) {
+                    return Some((most_significant_bits as u32) << 16 | previous as u32);
+                }
+                index
+            }
+            Err(index) => index,
+        };
+        self.data[..index].iter().rev().find_map(|container| {
+            let least_significant_bits = container.container.max()?;
+            Some((container.most_significant_bits as u32) << 16 | least_significant_bits as u32)
+        })
+    }
+
+    /// Same as [`RoaringBitmap::successor`].
+    pub fn next_after(&self, value: u32) -> Option<u32> {
+        self.successor(value)
+    }
+
+    /// Same as [`RoaringBitmap::predecessor`].
+    pub fn prev_before(&self, value: u32) -> Option<u32> {
+        self.predecessor(value)
+    }
+
+    /// Removes every value from the bitmap, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    /// Converts every container to a run-length encoded one when that takes less
+    /// space, and run containers back to arrays or bitmaps when they do not.
+    /// Returns `true` if the bitmap holds at least one run container afterwards.
+    pub fn run_optimize(&mut self) -> bool {
+        let mut has_runs = false;
+        for index in 0..self.data.len() {
+            let container = &mut self.data[index];
+            let original_type = std::mem::discriminant(&container.container);
+            let run_optimized = std::mem::take(&mut container.container).run_optimize();
+            has_runs |= matches!(run_optimized, ContainerType::ContainerTypeRun(_));
+            let converted = std::mem::discriminant(&run_optimized) != original_type;
+            container.container = run_optimized;
+            if converted {
+                self.debug_validate(index..index + 1);
+            }
+        }
+        has_runs
+    }
+}
+
+impl Default for RoaringBitmap {
+    fn default() -> Self {
+        RoaringBitmap::new()
+    }
+}
+
+impl Clone for RoaringBitmap {
+    fn clone(&self) -> Self {
+        RoaringBitmap {
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a RoaringBitmap {
+    type Item = u32;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for RoaringBitmap {
+    type Item = u32;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            containers: self.data.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl FromIterator<u32> for RoaringBitmap {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut bitmap = RoaringBitmap::new();
+        bitmap.extend(iter);
+        bitmap
+    }
+}
+
+impl Extend<u32> for RoaringBitmap {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+        for value in iter {
+            self.add(value);
+        }
+    }
+}
+
+/// An iterator over the values of a borrowed [`RoaringBitmap`], in ascending order.
+pub struct Iter<'a> {
+    containers: std::slice::Iter<'a, Container>,
+    current: Option<(&'a Container, ContainerCursor)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        loop {
+            if let Some((container, cursor)) = &mut self.current {
+                if let Some(value) = cursor.next(&container.container) {
+                    return Some((container.most_significant_bits as u32) << 16 | value as u32);
+                }
+            }
+            let container = self.containers.next()?;
+            self.current = Some((container, ContainerCursor::new(&container.container)));
+        }
+    }
+}
+
+/// An iterator over the values of an owned [`RoaringBitmap`], in ascending order.
+pub struct IntoIter {
+    containers: std::vec::IntoIter<Container>,
+    current: Option<(Container, ContainerCursor)>,
+}
+
+impl Iterator for IntoIter {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        loop {
+            if let Some((container, cursor)) = &mut self.current {
+                if let Some(value) = cursor.next(&container.container) {
+                    return Some((container.most_significant_bits as u32) << 16 | value as u32);
+                }
+            }
+            let container = self.containers.next()?;
+            let cursor = ContainerCursor::new(&container.container);
+            self.current = Some((container, cursor));
+        }
+    }
+}
+
+// Position of an iterator inside a single container. Values are decoded straight
+// from the container one at a time.
+enum ContainerCursor {
+    Array { index: usize },
+    // `word` holds the bits of `bitmap[index]` that have not been yielded yet.
+    Bitmap { index: usize, word: u64 },
+    // `value` is the next value to yield from `runs[index]`.
+    Run { index: usize, value: u32 },
+}
+
+impl ContainerCursor {
+    fn new(container: &ContainerType) -> Self {
+        match container {
+            ContainerType::ContainerTypeArray(_) => Self::Array { index: 0 },
+            ContainerType::ContainerTypeBitmap(bitmap_container) => Self::Bitmap {
+                index: 0,
+                word: bitmap_container.bitmap[0],
+            },
+            ContainerType::ContainerTypeRun(run_container) => Self::Run {
+                index: 0,
+                value: run_container.runs.first().map_or(0, |&(start, _)| start as u32),
+            },
+        }
+    }
+
+    fn next(&mut self, container: &ContainerType) -> Option<u16> {
+        match (self, container) {
+            (Self::Array { index }, ContainerType::ContainerTypeArray(array_container)) => {
+                let value = *array_container.array.get(*index)?;
+                *index += 1;
+                Some(value)
+            }
+            (Self::Bitmap { index, word }, ContainerType::ContainerTypeBitmap(bitmap_container)) => {
+                while *word == 0 {
+                    *word = *bitmap_container.bitmap.get(*index + 1)?;
+                    *index += 1;
+                }
+                let offset = word.trailing_zeros() as usize;
+                *word &= *word - 1;
+                Some((*index * 64 + offset) as u16)
+            }
+            (Self::Run { index, value }, ContainerType::ContainerTypeRun(run_container)) => {
+                let &(start, length) = run_container.runs.get(*index)?;
+                let current = *value;
+                if current == start as u32 + length as u32 {
+                    *index += 1;
+                    if let Some(&(next_start, _)) = run_container.runs.get(*index) {
+                        *value = next_start as u32;
+                    }
+                } else {
+                    *value += 1;
+                }
+                Some(current as u16)
+            }
+            _ => unreachable!("cursor does not match its container type"),
+        }
+    }
+}
+
+// Implements a set operator for every owned/borrowed combination of operands. Owned
+// left operands are updated in place through `$in_place` and returned, so their
+// containers are reused rather than cloned.
+macro_rules! impl_set_operator {
+    ($op:ident, $op_fn:ident, $op_assign:ident, $op_assign_fn:ident, $method:ident, $in_place:ident) => {
+        impl $op<&RoaringBitmap> for &RoaringBitmap {
+            type Output = RoaringBitmap;
+
+            fn $op_fn(self, rhs: &RoaringBitmap) -> RoaringBitmap {
+                self.$method(rhs)
+            }
+        }
+
+        impl $op<&RoaringBitmap> for RoaringBitmap {
+            type Output = RoaringBitmap;
+
+            fn $op_fn(mut self, rhs: &RoaringBitmap) -> RoaringBitmap {
+                self.$in_place(rhs);
+                self
+            }
+        }
+
+        impl $op<RoaringBitmap> for RoaringBitmap {
+            type Output = RoaringBitmap;
+
+            fn $op_fn(mut self, rhs: RoaringBitmap) -> RoaringBitmap {
+                self.$in_place(&rhs);
+                self
+            }
+        }
+
+        impl $op_assign<&RoaringBitmap> for RoaringBitmap {
+            fn $op_assign_fn(&mut self, rhs: &RoaringBitmap) {
+                self.$in_place(rhs);
+            }
+        }
+
+        impl $op_assign<RoaringBitmap> for RoaringBitmap {
+            fn $op_assign_fn(&mut self, rhs: RoaringBitmap) {
+                self.$in_place(&rhs);
+            }
+        }
+    };
+}
+
+impl_set_operator!(BitOr, bitor, BitOrAssign, bitor_assign, union, union_with);
+impl_set_operator!(BitAnd, bitand, BitAndAssign, bitand_assign, intersection, intersect_with);
+impl_set_operator!(Sub, sub, SubAssign, sub_assign, difference, difference_with);
+impl_set_operator!(BitXor, bitxor, BitXorAssign, bitxor_assign, symmetric_difference, symmetric_difference_with);
+
+// For the commutative operators a borrowed left operand can reuse the storage of
+// an owned right operand instead.
+impl BitOr<RoaringBitmap> for &RoaringBitmap {
+    type Output = RoaringBitmap;
+
+    fn bitor(self, mut rhs: RoaringBitmap) -> RoaringBitmap {
+        rhs.union_with(self);
+        rhs
+    }
+}
+
+impl BitAnd<RoaringBitmap> for &RoaringBitmap {
+    type Output = RoaringBitmap;
+
+    fn bitand(self, mut rhs: RoaringBitmap) -> RoaringBitmap {
+        rhs.intersect_with(self);
+        rhs
+    }
+}
+
+impl BitXor<RoaringBitmap> for &RoaringBitmap {
+    type Output = RoaringBitmap;
+
+    fn bitxor(self, mut rhs: RoaringBitmap) -> RoaringBitmap {
+        rhs.symmetric_difference_with(self);
+        rhs
+    }
+}
+
+impl Sub<RoaringBitmap> for &RoaringBitmap {
+    type Output = RoaringBitmap;
+
+    fn sub(self, rhs: RoaringBitmap) -> RoaringBitmap {
+        self.difference(&rhs)
+    }
+}
+
+#[derive(Debug)]
+struct Container {
+    most_significant_bits: u16,
+    container: ContainerType,
+}
+
+impl Clone for Container {
+    fn clone(&self) -> Self {
+        Container {
+            most_significant_bits: self.most_significant_bits,
+            container: self.container.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+enum ContainerType {
+    ContainerTypeArray(ArrayContainer),
+    ContainerTypeBitmap(BitmapContainer),
+    ContainerTypeRun(RunContainer),
+}
+
+impl Default for ContainerType {
+    fn default() -> Self { ContainerType::ContainerTypeArray(ArrayContainer::new(0)) }
+}
+
+impl ContainerType {
+    fn add(self, value: u16) -> Self {
+        match self {
+            Self::ContainerTypeArray(mut array_container) => {
+                // This check ensures that duplicate values do not cause unnecessary
+                // container conversion, because duplicate values will not increase the
+                // container size beyond 4096.
+                if array_container.array.len() == 4096 && !array_container.contains(&value) {
+                    let mut bitmap_container = BitmapContainer::from(array_container);
+                    bitmap_container.add(value);
+                    Self::ContainerTypeBitmap(bitmap_container)
+                } else {
+                    array_container.add(value);
+                    Self::ContainerTypeArray(array_container)
+                }
+            }
+            Self::ContainerTypeBitmap(mut bitmap_container) => {
+                bitmap_container.add(value);
+                Self::ContainerTypeBitmap(bitmap_container)
+            }
+            Self::ContainerTypeRun(mut run_container) => {
+                run_container.add(value);
+                Self::from_fragmented_run(run_container)
+            }
+        }
+    }
+
+    fn from_range(most_significant_bits: u16, start: u16, end: u16) -> Self {
+        let mut run_container = RunContainer::new(most_significant_bits);
+        run_container.push_run(start, end);
+        Self::from_run(run_container)
+    }
+
+    fn add_range(self, most_significant_bits: u16, start: u16, end: u16) -> Self {
+        if start == 0 && end == u16::MAX {
+            return Self::from_range(most_significant_bits, start, end);
+        }
+        match self {
+            Self::ContainerTypeBitmap(mut bitmap_container) => {
+                bitmap_container.add_range(start, end);
+                Self::ContainerTypeBitmap(bitmap_container)
+            }
+            container => {
+                let mut run_container = RunContainer::new(most_significant_bits);
+                run_container.push_run(start, end);
+                container.union_with(&Self::ContainerTypeRun(run_container))
+            }
+        }
+    }
+
+    fn flip_range(self, most_significant_bits: u16, start: u16, end: u16) -> Self {
+        match self {
+            Self::ContainerTypeBitmap(mut bitmap_container) => {
+                bitmap_container.flip_range(start, end);
+                Self::from_bitmap(bitmap_container)
+            }
+            container => {
+                let mut run_container = RunContainer::new(most_significant_bits);
+                run_container.push_run(start, end);
+                container.symmetric_difference_with(&Self::ContainerTypeRun(run_container))
+            }
+        }
+    }
+
+    fn remove_range(self, start: u16, end: u16) -> Self {
+        match self {
+            Self::ContainerTypeArray(mut array_container) => {
+                let lo = array_container.array.partition_point(|&value| value < start);
+                let hi = array_container.array.partition_point(|&value| value <= end);
+                array_container.array.drain(lo..hi);
+                Self::ContainerTypeArray(array_container)
+            }
+            Self::ContainerTypeBitmap(mut bitmap_container) => {
+                bitmap_container.remove_range(start, end);
+                Self::from_bitmap(bitmap_container)
+            }
+            Self::ContainerTypeRun(run_container) => {
+                let mut removed = RunContainer::new(run_container.most_significant_bits);
+                removed.push_run(start, end);
+                Self::difference_run_run(&run_container, &removed)
+            }
+        }
+    }
+
+    fn contains(&self, value: u16) -> bool {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.contains(&value),
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.contains(&value),
+            Self::ContainerTypeRun(run_container) => run_container.contains(&value),
+        }
+    }
+
+    fn remove(self, value: u16) -> Self {
+        match self {
+            Self::ContainerTypeArray(mut array_container) => {
+                array_container.remove(&value);
+                Self::ContainerTypeArray(array_container)
+            }
+            Self::ContainerTypeBitmap(mut bitmap_container) => {
+                bitmap_container.remove(&value);
+                if bitmap_container.cardinality == 4096 {
+                    let array_container = ArrayContainer::from(bitmap_container);
+                    Self::ContainerTypeArray(array_container)
+                } else {
+                    Self::ContainerTypeBitmap(bitmap_container)
+                }
+            }
+            Self::ContainerTypeRun(mut run_container) => {
+                run_container.remove(&value);
+                Self::from_fragmented_run(run_container)
+            }
+        }
+    }
+
+    fn union(&self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::union_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::union_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::union_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::union_array_bitmap(rhs, lhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::union_run_run(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::union_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::union_array_run(rhs, lhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::union_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::union_bitmap_run(rhs, lhs)
+            }
+        }
+    }
+
+    fn intersection(&self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersection_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersection_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersection_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersection_array_bitmap(rhs, lhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersection_run_run(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersection_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersection_array_run(rhs, lhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersection_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersection_bitmap_run(rhs, lhs)
+            }
+        }
+    }
+
+    // Merges containers that share a key. The words are ORed together without
+    // tracking the cardinality, which is counted once at the end.
+    fn union_many(most_significant_bits: u16, containers: &[&Container]) -> Self {
+        if let [container] = containers {
+            return container.container.clone();
+        }
+        if let Some(container) = containers.iter().find(|container| matches!(&container.container, Self::ContainerTypeRun(run_container) if run_container.is_full())) {
+            return container.container.clone();
+        }
+        let mut bitmap = Box::new([0u64; 1024]);
+        for container in containers {
+            container.container.for_each_word(|index, word| bitmap[index] |= word);
+        }
+        Self::from_bitmap(BitmapContainer::from_words(most_significant_bits, bitmap))
+    }
+
+    // Keeps the values found in at least `k` of `containers`. Each bit position has
+    // its own counter, stored bit-sliced: `counters[bit][index]` holds bit `bit` of
+    // the 64 counters for word `index`, so adding a word is a ripple-carry add over
+    // the slices and the final comparison against `k` works a word at a time.
+    fn at_least(most_significant_bits: u16, containers: &[&Container], k: usize) -> Self {
+        let slices = (usize::BITS - containers.len().leading_zeros()) as usize;
+        let mut counters: Vec<Box<[u64; 1024]>> = (0..slices).map(|_| Box::new([0u64; 1024])).collect();
+        for container in containers {
+            container.container.for_each_word(|index, word| {
+                let mut carry = word;
+                for counter in counters.iter_mut() {
+                    if carry == 0 {
+                        break;
+                    }
+                    let sum = counter[index] ^ carry;
+                    carry &= counter[index];
+                    counter[index] = sum;
+                }
+            });
+        }
+        // Compares the counters with `k` from the most significant slice down, keeping
+        // the bits whose counter is already known to be greater and those still equal.
+        let bitmap = Box::new(std::array::from_fn(|index| {
+            let mut greater = 0;
+            let mut equal = u64::MAX;
+            for (bit, counter) in counters.iter().enumerate().rev() {
+                if k >> bit & 1 == 1 {
+                    equal &= counter[index];
+                } else {
+                    greater |= equal & counter[index];
+                    equal &= !counter[index];
+                }
+            }
+            greater | equal
+        }));
+        Self::from_bitmap(BitmapContainer::from_words(most_significant_bits, bitmap))
+    }
+
+    // Calls `f` with the index and contents of the words of the container's bitmap
+    // representation, skipping words that are empty. An array container reports each
+    // of its values as a separate single-bit word.
+    fn for_each_word(&self, mut f: impl FnMut(usize, u64)) {
+        match self {
+            Self::ContainerTypeArray(array_container) => {
+                for value in &array_container.array {
+                    let (index, offset) = BitmapContainer::get_index_and_offset(value);
+                    f(index, 1 << offset);
+                }
+            }
+            Self::ContainerTypeBitmap(bitmap_container) => {
+                for (index, &word) in bitmap_container.bitmap.iter().enumerate() {
+                    if word != 0 {
+                        f(index, word);
+                    }
+                }
+            }
+            Self::ContainerTypeRun(run_container) => {
+                for &(start, length) in &run_container.runs {
+                    for (index, mask) in BitmapContainer::range_masks(start, start + length) {
+                        f(index, mask);
+                    }
+                }
+            }
+        }
+    }
+
+    fn intersection_len(&self, rhs: &Self) -> usize {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersection_len_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersection_len_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersection_len_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersection_len_array_bitmap(rhs, lhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersection_len_run_run(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersection_len_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersection_len_array_run(rhs, lhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersection_len_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersection_len_bitmap_run(rhs, lhs)
+            }
+        }
+    }
+
+    // A container with more values than `rhs` cannot be a subset of it, which also
+    // rules out a bitmap container inside an array container.
+    fn is_subset(&self, rhs: &Self) -> bool {
+        if self.cardinality() > rhs.cardinality() {
+            return false;
+        }
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::is_subset_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::is_subset_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::is_subset_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(_), Self::ContainerTypeArray(_)) => false,
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::is_subset_run_run(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::is_subset_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::is_subset_run_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::is_subset_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::is_subset_run_bitmap(lhs, rhs)
+            }
+        }
+    }
+
+    fn is_disjoint(&self, rhs: &Self) -> bool {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::is_disjoint_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::is_disjoint_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::is_disjoint_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::is_disjoint_array_bitmap(rhs, lhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::is_disjoint_run_run(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::is_disjoint_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::is_disjoint_array_run(rhs, lhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::is_disjoint_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::is_disjoint_bitmap_run(rhs, lhs)
+            }
+        }
+    }
+
+    fn difference(&self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::difference_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::difference_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::difference_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::difference_bitmap_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::difference_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::difference_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::difference_run_array(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::difference_run_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::difference_run_run(lhs, rhs)
+            }
+        }
+    }
+
+    fn symmetric_difference(&self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::symmetric_difference_array_array(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::symmetric_difference_bitmap_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::symmetric_difference_array_bitmap(lhs, rhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::symmetric_difference_array_bitmap(rhs, lhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::symmetric_difference_run_run(lhs, rhs)
+            }
+            (Self::ContainerTypeArray(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::symmetric_difference_array_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::symmetric_difference_array_run(rhs, lhs)
+            }
+            (Self::ContainerTypeBitmap(lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::symmetric_difference_bitmap_run(lhs, rhs)
+            }
+            (Self::ContainerTypeRun(lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::symmetric_difference_bitmap_run(rhs, lhs)
+            }
+        }
+    }
+
+    // The `*_with` operations reuse the storage of `self` where its container type
+    // allows it, and fall back to the allocating kernels otherwise.
+    fn union_with(self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::union_with_bitmap_array(&mut lhs, rhs);
+                Self::ContainerTypeBitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::union_with_bitmap_bitmap(&mut lhs, rhs);
+                Self::ContainerTypeBitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeRun(rhs)) if !rhs.is_full() => {
+                Self::union_with_bitmap_run(&mut lhs, rhs);
+                Self::ContainerTypeBitmap(lhs)
+            }
+            (lhs, rhs) => lhs.union(rhs),
+        }
+    }
+
+    fn intersect_with(self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(mut lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::intersect_with_array_array(&mut lhs, rhs);
+                Self::ContainerTypeArray(lhs)
+            }
+            (Self::ContainerTypeArray(mut lhs), Self::ContainerTypeBitmap(rhs)) => {
+                lhs.array.retain(|value| rhs.contains(value));
+                Self::ContainerTypeArray(lhs)
+            }
+            (Self::ContainerTypeArray(mut lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersect_with_array_run(&mut lhs, rhs);
+                Self::ContainerTypeArray(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::intersect_with_bitmap_bitmap(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::intersect_with_bitmap_run(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (lhs, rhs) => lhs.intersection(rhs),
+        }
+    }
+
+    fn difference_with(self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeArray(mut lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::difference_with_array_array(&mut lhs, rhs);
+                Self::ContainerTypeArray(lhs)
+            }
+            (Self::ContainerTypeArray(mut lhs), Self::ContainerTypeBitmap(rhs)) => {
+                lhs.array.retain(|value| !rhs.contains(value));
+                Self::ContainerTypeArray(lhs)
+            }
+            (Self::ContainerTypeArray(mut lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::difference_with_array_run(&mut lhs, rhs);
+                Self::ContainerTypeArray(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::difference_with_bitmap_array(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::difference_with_bitmap_bitmap(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::difference_with_bitmap_run(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (lhs, rhs) => lhs.difference(rhs),
+        }
+    }
+
+    fn symmetric_difference_with(self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeArray(rhs)) => {
+                Self::symmetric_difference_with_bitmap_array(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeBitmap(rhs)) => {
+                Self::symmetric_difference_with_bitmap_bitmap(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (Self::ContainerTypeBitmap(mut lhs), Self::ContainerTypeRun(rhs)) => {
+                Self::symmetric_difference_with_bitmap_run(&mut lhs, rhs);
+                Self::from_bitmap(lhs)
+            }
+            (lhs, rhs) => lhs.symmetric_difference(rhs),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.array.is_empty(),
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.cardinality == 0,
+            Self::ContainerTypeRun(run_container) => run_container.runs.is_empty(),
+        }
+    }
+
+    // The length of an array container's vector is its cardinality, so only bitmap
+    // and run containers carry a separate count.
+    fn cardinality(&self) -> usize {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.array.len(),
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.cardinality,
+            Self::ContainerTypeRun(run_container) => run_container.cardinality,
+        }
+    }
+
+    fn min(&self) -> Option<u16> {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.array.first().copied(),
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.next_from(0),
+            Self::ContainerTypeRun(run_container) => run_container.runs.first().map(|&(start, _)| start),
+        }
+    }
+
+    fn max(&self) -> Option<u16> {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.array.last().copied(),
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.previous_from(u16::MAX),
+            Self::ContainerTypeRun(run_container) => run_container.runs.last().map(|&(start, length)| start + length),
+        }
+    }
+
+    // Smallest value in the container that is greater than or equal to `value`.
+    fn next_from(&self, value: u16) -> Option<u16> {
+        match self {
+            Self::ContainerTypeArray(array_container) => {
+                let index = array_container.array.partition_point(|&x| x < value);
+                array_container.array.get(index).copied()
+            }
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.next_from(value),
+            Self::ContainerTypeRun(run_container) => run_container.next_from(value),
+        }
+    }
+
+    // Largest value in the container that is less than or equal to `value`.
+    fn previous_from(&self, value: u16) -> Option<u16> {
+        match self {
+            Self::ContainerTypeArray(array_container) => {
+                let index = array_container.array.partition_point(|&x| x <= value);
+                index.checked_sub(1).map(|index| array_container.array[index])
+            }
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.previous_from(value),
+            Self::ContainerTypeRun(run_container) => run_container.previous_from(value),
+        }
+    }
+
+    // Number of values in the container that are less than or equal to `value`.
+    fn rank(&self, value: u16) -> usize {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.array.partition_point(|&x| x <= value),
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.rank(value),
+            Self::ContainerTypeRun(run_container) => run_container.rank(value),
+        }
+    }
+
+    // The `n`-th smallest value in the container, which must hold more than `n` values.
+    fn select(&self, n: usize) -> u16 {
+        match self {
+            Self::ContainerTypeArray(array_container) => array_container.array[n],
+            Self::ContainerTypeBitmap(bitmap_container) => bitmap_container.select(n),
+            Self::ContainerTypeRun(run_container) => run_container.select(n),
+        }
+    }
+
+    fn run_optimize(self) -> Self {
+        match self {
+            Self::ContainerTypeArray(array_container) => {
+                let number_of_runs = array_container.number_of_runs();
+                if RunContainer::size_in_bytes(number_of_runs) < ArrayContainer::size_in_bytes(array_container.array.len()) {
+                    Self::ContainerTypeRun(RunContainer::from(array_container))
+                } else {
+                    Self::ContainerTypeArray(array_container)
+                }
+            }
+            Self::ContainerTypeBitmap(bitmap_container) => {
+                let number_of_runs = bitmap_container.number_of_runs();
+                if RunContainer::size_in_bytes(number_of_runs) < BitmapContainer::SIZE_IN_BYTES {
+                    Self::ContainerTypeRun(RunContainer::from(bitmap_container))
+                } else {
+                    Self::ContainerTypeBitmap(bitmap_container)
+                }
+            }
+            Self::ContainerTypeRun(run_container) => Self::from_run(run_container),
+        }
+    }
+
+    // Wraps sorted, unique values in an array container, or a bitmap container when
+    // there are too many for an array.
+    fn from_sorted_array(most_significant_bits: u16, array: Vec<u16>) -> Self {
+        let array_container = ArrayContainer { most_significant_bits, array };
+        if array_container.array.len() > 4096 {
+            Self::ContainerTypeBitmap(BitmapContainer::from(array_container))
+        } else {
+            Self::ContainerTypeArray(array_container)
+        }
+    }
+
+    // Downgrades `bitmap_container` to an array container once its values fit in one.
+    fn from_bitmap(bitmap_container: BitmapContainer) -> Self {
+        if bitmap_container.cardinality > 4096 {
+            Self::ContainerTypeBitmap(bitmap_container)
+        } else {
+            Self::ContainerTypeArray(ArrayContainer::from(bitmap_container))
+        }
+    }
+
+    // Picks the smallest of the three representations for the values in `run_container`.
+    fn from_run(run_container: RunContainer) -> Self {
+        let cardinality = run_container.cardinality;
+        let run_size = RunContainer::size_in_bytes(run_container.runs.len());
+        if cardinality <= 4096 {
+            if run_size < ArrayContainer::size_in_bytes(cardinality) {
+                Self::ContainerTypeRun(run_container)
+            } else {
+                Self::ContainerTypeArray(ArrayContainer::from(run_container))
+            }
+        } else if run_size < BitmapContainer::SIZE_IN_BYTES {
+            Self::ContainerTypeRun(run_container)
+        } else {
+            Self::ContainerTypeBitmap(BitmapContainer::from(run_container))
+        }
+    }
+
+    // Single value updates can split a run container into more runs than fit in the
+    // space of a bitmap, at which point it stops being worth keeping as runs.
+    fn from_fragmented_run(run_container: RunContainer) -> Self {
+        if run_container.runs.len() > RunContainer::MAX_RUNS {
+            Self::from_run(run_container)
+        } else {
+            Self::ContainerTypeRun(run_container)
+        }
+    }
+}
+
+impl Clone for ContainerType {
+    fn clone(&self) -> Self {
+        match self {
+            Self::ContainerTypeArray(array_container) => Self::ContainerTypeArray(array_container.clone()),
+            Self::ContainerTypeBitmap(bitmap_container) => Self::ContainerTypeBitmap(bitmap_container.clone()),
+            Self::ContainerTypeRun(run_container) => Self::ContainerTypeRun(run_container.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ArrayContainer {
+    most_significant_bits: u16,
+    array: Vec<u16>,
+}
+
+impl ArrayContainer {
+    fn new(most_significant_bits: u16) -> Self {
+        ArrayContainer {
+            most_significant_bits,
+            array: Vec::new(),
+        }
+    }
+    fn add(&mut self, value: u16) {
+        if let Err(index) = self.array.binary_search(&value) {
+            self.array.insert(index, value);
+        }
+    }
+
+    fn contains(&self, value: &u16) -> bool {
+        self.array.binary_search(value).is_ok()
+    }
+
+    fn remove(&mut self, value: &u16) {
+        self.array.binary_search(value).ok().map(|index| self.array.remove(index));
+    }
+
+    fn number_of_runs(&self) -> usize {
+        let mut number_of_runs = 0;
+        let mut previous: Option<u16> = None;
+        for &value in &self.array {
+            if previous.is_none_or(|previous| previous + 1 != value) {
+                number_of_runs += 1;
+            }
+            previous = Some(value);
+        }
+        number_of_runs
+    }
+
+    fn size_in_bytes(cardinality: usize) -> usize {
+        2 * cardinality
+    }
+}
+
+impl From<BitmapContainer> for ArrayContainer {
+    fn from(bitmap_container: BitmapContainer) -> Self {
+        let mut array = Vec::with_capacity(bitmap_container.cardinality);
+        for (index, bitmap) in bitmap_container.bitmap.iter().enumerate() {
+            if *bitmap == 0 {
                 continue;
             }
 
-            let indices = Self::extract_set_bit_indices(bitmap.clone());
-            for offset in indices {
-                array.push((index * 64 + offset) as u16);
+            let indices = Self::extract_set_bit_indices(*bitmap);
+            for offset in indices {
+                array.push((index * 64 + offset) as u16);
+            }
+        }
+        ArrayContainer {
+            most_significant_bits: bitmap_container.most_significant_bits,
+            array,
+        }
+    }
+}
+
+impl From<RunContainer> for ArrayContainer {
+    fn from(run_container: RunContainer) -> Self {
+        let mut array = Vec::with_capacity(run_container.cardinality);
+        for &(start, length) in &run_container.runs {
+            array.extend(start..=start + length);
+        }
+        ArrayContainer {
+            most_significant_bits: run_container.most_significant_bits,
+            array,
+        }
+    }
+}
+
+// Array intersections. A linear merge touches every value of both arrays, so once
+// one array is much larger than the other it is cheaper to gallop through the
+// larger one, skipping ahead exponentially for each value of the smaller one.
+impl ArrayContainer {
+    // Size ratio from which galloping beats a linear merge.
+    const GALLOPING_RATIO: usize = 64;
+
+    fn is_skewed(smaller: &[u16], larger: &[u16]) -> bool {
+        smaller.len() * Self::GALLOPING_RATIO < larger.len()
+    }
+
+    // Appends the values found in either array to `res`.
+    fn union_merge(lhs: &[u16], rhs: &[u16], res: &mut Vec<u16>) {
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.len() && rhs_idx < rhs.len() {
+            let lhs_value = lhs[lhs_idx];
+            let rhs_value = rhs[rhs_idx];
+            if lhs_value == rhs_value {
+                res.push(lhs_value);
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_value < rhs_value {
+                res.push(lhs_value);
+                lhs_idx += 1;
+            } else {
+                res.push(rhs_value);
+                rhs_idx += 1;
+            }
+        }
+        res.extend_from_slice(&lhs[lhs_idx..]);
+        res.extend_from_slice(&rhs[rhs_idx..]);
+    }
+
+    // Appends the values found in both arrays to `res`.
+    fn intersect_merge(lhs: &[u16], rhs: &[u16], res: &mut Vec<u16>) {
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.len() && rhs_idx < rhs.len() {
+            let lhs_value = lhs[lhs_idx];
+            let rhs_value = rhs[rhs_idx];
+            if lhs_value == rhs_value {
+                res.push(lhs_value);
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_value < rhs_value {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+    }
+
+    // Appends the values found in both arrays to `res`, galloping through `larger`.
+    fn intersect_galloping(smaller: &[u16], larger: &[u16], res: &mut Vec<u16>) {
+        let mut larger_idx = 0;
+        for &value in smaller {
+            larger_idx = Self::advance_until(larger, larger_idx, value);
+            if larger_idx == larger.len() {
+                break;
+            }
+            if larger[larger_idx] == value {
+                res.push(value);
+            }
+        }
+    }
+
+    // Number of values found in both arrays.
+    fn intersect_merge_len(lhs: &[u16], rhs: &[u16]) -> usize {
+        let mut res = 0;
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.len() && rhs_idx < rhs.len() {
+            let lhs_value = lhs[lhs_idx];
+            let rhs_value = rhs[rhs_idx];
+            if lhs_value == rhs_value {
+                res += 1;
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_value < rhs_value {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        res
+    }
+
+    // Number of values found in both arrays, galloping through `larger`.
+    fn intersect_galloping_len(smaller: &[u16], larger: &[u16]) -> usize {
+        let mut res = 0;
+        let mut larger_idx = 0;
+        for &value in smaller {
+            larger_idx = Self::advance_until(larger, larger_idx, value);
+            if larger_idx == larger.len() {
+                break;
+            }
+            res += (larger[larger_idx] == value) as usize;
+        }
+        res
+    }
+
+    // Index of the first value at or after `start` that is not less than `value`.
+    // Doubles the step until it overshoots, then binary searches the last step.
+    fn advance_until(array: &[u16], start: usize, value: u16) -> usize {
+        if start >= array.len() || array[start] >= value {
+            return start;
+        }
+        let mut low = start;
+        let mut step = 1;
+        while low + step < array.len() && array[low + step] < value {
+            low += step;
+            step *= 2;
+        }
+        let high = (low + step).min(array.len());
+        low + 1 + array[low + 1..high].partition_point(|&x| x < value)
+    }
+
+    fn extract_set_bit_indices(mut bitmap: u64) -> impl Iterator<Item = usize> {
+        std::iter::from_fn(move || {
+            if bitmap == 0 {
+                return None;
+            }
+            let lsb_bit_index = bitmap.trailing_zeros() as usize;
+            bitmap &= bitmap - 1;
+            Some(lsb_bit_index)
+        })
+    }
+}
+
+impl Clone for ArrayContainer {
+    fn clone(&self) -> Self {
+        ArrayContainer {
+            most_significant_bits: self.most_significant_bits,
+            array: self.array.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BitmapContainer {
+    most_significant_bits: u16,
+    cardinality: usize,
+    // One bit for each of the 2^16 values of the container.
+    bitmap: Box<[u64; 1024]>,
+}
+
+impl BitmapContainer {
+    fn new(most_significant_bits: u16) -> Self {
+        BitmapContainer {
+            most_significant_bits,
+            cardinality: 0,
+            bitmap: Box::new([0; 1024]),
+        }
+    }
+
+    // Takes ownership of a block of words, counting its set bits.
+    fn from_words(most_significant_bits: u16, bitmap: Box<[u64; 1024]>) -> Self {
+        let mut bitmap_container = BitmapContainer { most_significant_bits, cardinality: 0, bitmap };
+        bitmap_container.cardinality = bitmap_container.count_set_bits();
+        bitmap_container
+    }
+
+    fn count_set_bits(&self) -> usize {
+        simd::popcount(&self.bitmap)
+    }
+
+    fn popcount(bitmap: &[u64; 1024]) -> usize {
+        bitmap.iter().map(|bitmap| bitmap.count_ones() as usize).sum()
+    }
+
+    fn add(&mut self, value: u16) {
+        let (index, offset) = Self::get_index_and_offset(&value);
+        let before_bitwise_op = self.bitmap[index];
+        self.bitmap[index] |= 1 << offset;
+        let after_bitwise_op = self.bitmap[index];
+        self.cardinality += (before_bitwise_op != after_bitwise_op) as usize;
+    }
+
+    fn contains(&self, value: &u16) -> bool {
+        let (index, offset) = Self::get_index_and_offset(value);
+        self.bitmap[index] & (1 << offset) != 0
+    }
+
+    fn remove(&mut self, value: &u16) {
+        let (index, offset) = Self::get_index_and_offset(value);
+        let before_bitwise_op = self.bitmap[index];
+        self.bitmap[index] &= !(1 << offset);
+        let after_bitwise_op = self.bitmap[index];
+        self.cardinality -= (before_bitwise_op != after_bitwise_op) as usize;
+    }
+
+    fn get_index_and_offset(value: &u16) -> (usize, usize) {
+        (*value as usize / 64, *value as usize % 64)
+    }
+
+    const SIZE_IN_BYTES: usize = 8192;
+
+    // Splits start..=end into the words it touches, as (index, mask) pairs.
+    fn range_masks(start: u16, end: u16) -> impl Iterator<Item = (usize, u64)> {
+        let (start_index, start_offset) = Self::get_index_and_offset(&start);
+        let (end_index, end_offset) = Self::get_index_and_offset(&end);
+        (start_index..=end_index).map(move |index| {
+            let mut mask = u64::MAX;
+            if index == start_index {
+                mask &= u64::MAX << start_offset;
+            }
+            if index == end_index {
+                mask &= u64::MAX >> (63 - end_offset);
+            }
+            (index, mask)
+        })
+    }
+
+    // Whether any bit in start..=end is set.
+    fn intersects_range(&self, start: u16, end: u16) -> bool {
+        Self::range_masks(start, end).any(|(index, mask)| self.bitmap[index] & mask != 0)
+    }
+
+    // Sets every bit in start..=end a word at a time.
+    fn add_range(&mut self, start: u16, end: u16) {
+        for (index, mask) in Self::range_masks(start, end) {
+            let original_set_bits_count = self.bitmap[index].count_ones();
+            self.bitmap[index] |= mask;
+            self.cardinality += (self.bitmap[index].count_ones() - original_set_bits_count) as usize;
+        }
+    }
+
+    // Clears every bit in start..=end a word at a time.
+    fn remove_range(&mut self, start: u16, end: u16) {
+        for (index, mask) in Self::range_masks(start, end) {
+            let original_set_bits_count = self.bitmap[index].count_ones();
+            self.bitmap[index] &= !mask;
+            self.cardinality -= (original_set_bits_count - self.bitmap[index].count_ones()) as usize;
+        }
+    }
+
+    // Toggles every bit in start..=end a word at a time.
+    fn flip_range(&mut self, start: u16, end: u16) {
+        for (index, mask) in Self::range_masks(start, end) {
+            let original_set_bits_count = self.bitmap[index].count_ones() as usize;
+            self.bitmap[index] ^= mask;
+            self.cardinality = self.cardinality + self.bitmap[index].count_ones() as usize - original_set_bits_count;
+        }
+    }
+
+    // Popcount of every word before the one holding `value`, plus the bits of that
+    // word up to and including `value`.
+    fn rank(&self, value: u16) -> usize {
+        let (index, offset) = Self::get_index_and_offset(&value);
+        let preceding: usize = self.bitmap[..index].iter().map(|bitmap| bitmap.count_ones() as usize).sum();
+        preceding + (self.bitmap[index] & (u64::MAX >> (63 - offset))).count_ones() as usize
+    }
+
+    // Masks off the bits below `value` in its word, then skips ahead to the first
+    // non-zero word.
+    fn next_from(&self, value: u16) -> Option<u16> {
+        let (index, offset) = Self::get_index_and_offset(&value);
+        let mut bitmap = self.bitmap[index] & (u64::MAX << offset);
+        let mut index = index;
+        while bitmap == 0 {
+            index += 1;
+            bitmap = *self.bitmap.get(index)?;
+        }
+        Some((index * 64 + bitmap.trailing_zeros() as usize) as u16)
+    }
+
+    // Masks off the bits above `value` in its word, then skips back to the last
+    // non-zero word.
+    fn previous_from(&self, value: u16) -> Option<u16> {
+        let (index, offset) = Self::get_index_and_offset(&value);
+        let mut index = index;
+        let mut bitmap = self.bitmap[index] & (u64::MAX >> (63 - offset));
+        while bitmap == 0 {
+            index = index.checked_sub(1)?;
+            bitmap = self.bitmap[index];
+        }
+        Some((index * 64 + 63 - bitmap.leading_zeros() as usize) as u16)
+    }
+
+    fn select(&self, n: usize) -> u16 {
+        let mut remaining = n;
+        for (index, &bitmap) in self.bitmap.iter().enumerate() {
+            let set_bits_count = bitmap.count_ones() as usize;
+            if remaining < set_bits_count {
+                let mut bitmap = bitmap;
+                for _ in 0..remaining {
+                    bitmap &= bitmap - 1;
+                }
+                return (index * 64 + bitmap.trailing_zeros() as usize) as u16;
+            }
+            remaining -= set_bits_count;
+        }
+        unreachable!("select({n}) on a bitmap container holding {} values", self.cardinality)
+    }
+
+    // A run starts at every set bit whose preceding bit is clear.
+    fn number_of_runs(&self) -> usize {
+        let mut number_of_runs = 0;
+        let mut carry = 0;
+        for &bitmap in self.bitmap.iter() {
+            number_of_runs += (bitmap & !((bitmap << 1) | carry)).count_ones() as usize;
+            carry = bitmap >> 63;
+        }
+        number_of_runs
+    }
+}
+
+impl From<ArrayContainer> for BitmapContainer {
+    fn from(array_container: ArrayContainer) -> Self {
+        let mut bitmap_container = BitmapContainer::new(array_container.most_significant_bits);
+        for &value in &array_container.array {
+            let (index, offset) = BitmapContainer::get_index_and_offset(&value);
+            bitmap_container.bitmap[index] |= 1 << offset;
+        }
+        bitmap_container.cardinality = array_container.array.len();
+        bitmap_container
+    }
+}
+
+impl From<RunContainer> for BitmapContainer {
+    fn from(run_container: RunContainer) -> Self {
+        let mut bitmap_container = BitmapContainer::new(run_container.most_significant_bits);
+        for &(start, length) in &run_container.runs {
+            bitmap_container.add_range(start, start + length);
+        }
+        bitmap_container
+    }
+}
+
+impl Clone for BitmapContainer {
+    fn clone(&self) -> Self {
+        BitmapContainer {
+            most_significant_bits: self.most_significant_bits,
+            cardinality: self.cardinality,
+            bitmap: self.bitmap.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RunContainer {
+    most_significant_bits: u16,
+    // Sorted runs stored as (start, length), each covering start..=start + length.
+    // Runs never overlap or touch, so every run is separated by at least one gap.
+    runs: Vec<(u16, u16)>,
+    // Total number of values covered by `runs`, kept up to date by every update.
+    cardinality: usize,
+}
+
+impl RunContainer {
+    // Past this many runs a run container is larger than a bitmap container.
+    const MAX_RUNS: usize = 2047;
+
+    fn new(most_significant_bits: u16) -> Self {
+        RunContainer {
+            most_significant_bits,
+            runs: Vec::new(),
+            cardinality: 0,
+        }
+    }
+
+    fn add(&mut self, value: u16) {
+        let index = self.runs.partition_point(|&(start, _)| start <= value);
+        if index > 0 {
+            let (start, length) = self.runs[index - 1];
+            let end = start as u32 + length as u32;
+            if value as u32 <= end {
+                return;
+            }
+            if value as u32 == end + 1 {
+                self.cardinality += 1;
+                self.runs[index - 1].1 += 1;
+                if index < self.runs.len() && self.runs[index].0 as u32 == value as u32 + 1 {
+                    let (_, next_length) = self.runs.remove(index);
+                    self.runs[index - 1].1 += next_length + 1;
+                }
+                return;
+            }
+        }
+        self.cardinality += 1;
+        if index < self.runs.len() && self.runs[index].0 as u32 == value as u32 + 1 {
+            self.runs[index].0 = value;
+            self.runs[index].1 += 1;
+        } else {
+            self.runs.insert(index, (value, 0));
+        }
+    }
+
+    fn contains(&self, value: &u16) -> bool {
+        let index = self.runs.partition_point(|&(start, _)| start <= *value);
+        index > 0 && {
+            let (start, length) = self.runs[index - 1];
+            *value - start <= length
+        }
+    }
+
+    fn remove(&mut self, value: &u16) {
+        let index = self.runs.partition_point(|&(start, _)| start <= *value);
+        if index == 0 {
+            return;
+        }
+        let (start, length) = self.runs[index - 1];
+        if *value - start > length {
+            return;
+        }
+        let end = start + length;
+        self.cardinality -= 1;
+        if length == 0 {
+            self.runs.remove(index - 1);
+        } else if *value == start {
+            self.runs[index - 1] = (start + 1, length - 1);
+        } else if *value == end {
+            self.runs[index - 1].1 -= 1;
+        } else {
+            self.runs[index - 1].1 = *value - start - 1;
+            self.runs.insert(index, (*value + 1, end - *value - 1));
+        }
+    }
+
+    fn rank(&self, value: u16) -> usize {
+        let mut rank = 0;
+        for &(start, length) in &self.runs {
+            if start > value {
+                break;
+            }
+            rank += (value - start).min(length) as usize + 1;
+        }
+        rank
+    }
+
+    fn next_from(&self, value: u16) -> Option<u16> {
+        let index = self.runs.partition_point(|&(start, _)| start <= value);
+        if index > 0 {
+            let (start, length) = self.runs[index - 1];
+            if value - start <= length {
+                return Some(value);
+            }
+        }
+        self.runs.get(index).map(|&(start, _)| start)
+    }
+
+    fn previous_from(&self, value: u16) -> Option<u16> {
+        let index = self.runs.partition_point(|&(start, _)| start <= value);
+        let (start, length) = self.runs[..index].last()?;
+        Some(value.min(start + length))
+    }
+
+    fn select(&self, n: usize) -> u16 {
+        let mut remaining = n;
+        for &(start, length) in &self.runs {
+            if remaining <= length as usize {
+                return start + remaining as u16;
+            }
+            remaining -= length as usize + 1;
+        }
+        unreachable!("select({n}) on a run container holding {} values", self.cardinality)
+    }
+
+    fn is_full(&self) -> bool {
+        self.runs.len() == 1 && self.runs[0] == (0, u16::MAX)
+    }
+
+    // Appends start..=end, which must not start before the last run, merging it
+    // into the last run when they overlap or touch.
+    fn push_run(&mut self, start: u16, end: u16) {
+        if let Some(last) = self.runs.last_mut() {
+            let last_end = last.0 as u32 + last.1 as u32;
+            if start as u32 <= last_end + 1 {
+                if end as u32 > last_end {
+                    self.cardinality += (end as u32 - last_end) as usize;
+                    last.1 = end - last.0;
+                }
+                return;
+            }
+        }
+        self.cardinality += (end - start) as usize + 1;
+        self.runs.push((start, end - start));
+    }
+
+    fn size_in_bytes(number_of_runs: usize) -> usize {
+        2 + 4 * number_of_runs
+    }
+
+    // Builds the runs covered by exactly one of two sets of half-open ranges, given
+    // as sorted start, end, start, end, ... boundaries. Every boundary toggles
+    // whether the values that follow it are in the result.
+    fn from_boundaries(
+        most_significant_bits: u16,
+        lhs: impl Iterator<Item = u32>,
+        rhs: impl Iterator<Item = u32>,
+    ) -> Self {
+        let mut res = RunContainer::new(most_significant_bits);
+        let mut lhs = lhs.peekable();
+        let mut rhs = rhs.peekable();
+        let mut run_start = None;
+        loop {
+            let boundary = match (lhs.peek(), rhs.peek()) {
+                (Some(&lhs_boundary), Some(&rhs_boundary)) if lhs_boundary <= rhs_boundary => lhs.next(),
+                (Some(_), Some(_)) => rhs.next(),
+                (Some(_), None) => lhs.next(),
+                (None, _) => rhs.next(),
+            };
+            let Some(boundary) = boundary else {
+                break;
+            };
+            run_start = match run_start {
+                None => Some(boundary),
+                Some(start) => {
+                    if boundary > start {
+                        res.push_run(start as u16, (boundary - 1) as u16);
+                    }
+                    None
+                }
+            };
+        }
+        res
+    }
+}
+
+impl From<ArrayContainer> for RunContainer {
+    fn from(array_container: ArrayContainer) -> Self {
+        let mut run_container = RunContainer::new(array_container.most_significant_bits);
+        for &value in &array_container.array {
+            run_container.push_run(value, value);
+        }
+        run_container
+    }
+}
+
+impl From<BitmapContainer> for RunContainer {
+    fn from(bitmap_container: BitmapContainer) -> Self {
+        let mut run_container = RunContainer::new(bitmap_container.most_significant_bits);
+        let bitmap = &bitmap_container.bitmap;
+        let mut index = 0;
+        let mut current = bitmap[0];
+        loop {
+            while current == 0 && index + 1 < bitmap.len() {
+                index += 1;
+                current = bitmap[index];
+            }
+            if current == 0 {
+                break;
+            }
+            let start = index * 64 + current.trailing_zeros() as usize;
+            // Fill in the zeros below the lowest set bit, so the run ends at the
+            // first zero of the result.
+            current |= current - 1;
+            while current == u64::MAX && index + 1 < bitmap.len() {
+                index += 1;
+                current = bitmap[index];
+            }
+            if current == u64::MAX {
+                run_container.push_run(start as u16, (index * 64 + 63) as u16);
+                break;
+            }
+            let end = index * 64 + (!current).trailing_zeros() as usize;
+            run_container.push_run(start as u16, (end - 1) as u16);
+            // Clear the run just recorded.
+            current &= current + 1;
+        }
+        run_container
+    }
+}
+
+impl Clone for RunContainer {
+    fn clone(&self) -> Self {
+        RunContainer {
+            most_significant_bits: self.most_significant_bits,
+            runs: self.runs.clone(),
+            cardinality: self.cardinality,
+        }
+    }
+}
+
+impl ContainerType {
+    fn union_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> Self {
+        let sum = lhs.array.len() + rhs.array.len();
+        if sum > 4096 {
+            let mut res = BitmapContainer::new(lhs.most_significant_bits);
+            let mut lhs_idx = 0;
+            let mut rhs_idx = 0;
+            while lhs_idx < lhs.array.len() && rhs_idx < rhs.array.len() {
+                let lhs_value = lhs.array[lhs_idx];
+                let rhs_value = rhs.array[rhs_idx];
+                if lhs_value == rhs_value {
+                    res.add(lhs_value);
+                    lhs_idx += 1;
+                    rhs_idx += 1;
+                } else if lhs_value < rhs_value {
+                    res.add(lhs_value);
+                    lhs_idx += 1;
+                } else {
+                    res.add(rhs_value);
+                    rhs_idx += 1;
+                }
+            }
+            while lhs_idx < lhs.array.len() {
+                res.add(lhs.array[lhs_idx]);
+                lhs_idx += 1;
+            }
+            while rhs_idx < rhs.array.len() {
+                res.add(rhs.array[rhs_idx]);
+                rhs_idx += 1;
+            }
+            if res.cardinality > 4096 {
+                Self::ContainerTypeBitmap(res)
+            } else {
+                Self::ContainerTypeArray(ArrayContainer::from(res))
+            }
+        } else {
+            let mut res = ArrayContainer {
+                most_significant_bits: lhs.most_significant_bits,
+                array: Vec::with_capacity(sum),
+            };
+            simd::union(&lhs.array, &rhs.array, &mut res.array);
+            Self::ContainerTypeArray(res)
+        }
+    }
+
+    fn union_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> Self {
+        const SELECT: bool = true;
+        if SELECT {
+            Self::approach_paper(lhs, rhs)
+        } else {
+            Self::approach_custom(lhs, rhs)
+        }
+    }
+
+    /*
+    This is an Alternate approach wherein we take a clone of lhs and OR the words of rhs into it in place,
+    then recount the set bits, instead of building a fresh block.
+     */
+    fn approach_custom(lhs: &BitmapContainer, rhs: &BitmapContainer) -> ContainerType {
+        let mut res = lhs.clone();
+        for (res_bitmap, rhs_bitmap) in res.bitmap.iter_mut().zip(rhs.bitmap.iter()) {
+            *res_bitmap |= rhs_bitmap;
+        }
+        res.cardinality = res.count_set_bits();
+        Self::ContainerTypeBitmap(res)
+    }
+
+    fn approach_paper(lhs: &BitmapContainer, rhs: &BitmapContainer) -> ContainerType {
+        let bitmap = Box::new(std::array::from_fn(|index| lhs.bitmap[index] | rhs.bitmap[index]));
+        Self::ContainerTypeBitmap(BitmapContainer::from_words(lhs.most_significant_bits, bitmap))
+    }
+
+    fn union_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> Self {
+        let mut res = rhs.clone();
+        for value in lhs.array.iter() {
+            res.add(*value);
+        }
+        Self::ContainerTypeBitmap(res)
+    }
+
+    fn intersection_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> Self {
+        let (smaller_array, larger_array) = if lhs.array.len() > rhs.array.len() {
+            (rhs, lhs)
+        } else {
+            (lhs, rhs)
+        };
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::with_capacity(smaller_array.array.len()),
+        };
+        if ArrayContainer::is_skewed(&smaller_array.array, &larger_array.array) {
+            ArrayContainer::intersect_galloping(&smaller_array.array, &larger_array.array, &mut res.array);
+        } else {
+            simd::intersect(&lhs.array, &rhs.array, &mut res.array);
+        }
+        Self::ContainerTypeArray(res)
+    }
+
+    fn intersection_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> Self {
+        let cardinality: usize = lhs.bitmap.iter().zip(rhs.bitmap.iter()).map(|(lhs_bitmap, rhs_bitmap)| (lhs_bitmap & rhs_bitmap).count_ones() as usize).sum();
+        if cardinality > 4096 {
+            let bitmap = Box::new(std::array::from_fn(|index| lhs.bitmap[index] & rhs.bitmap[index]));
+            Self::ContainerTypeBitmap(BitmapContainer {
+                most_significant_bits: lhs.most_significant_bits,
+                cardinality,
+                bitmap,
+            })
+        } else {
+            let mut res = ArrayContainer {
+                most_significant_bits: lhs.most_significant_bits,
+                array: Vec::with_capacity(cardinality),
+            };
+            for (index, (lhs_bitmap, rhs_bitmap)) in lhs.bitmap.iter().zip(rhs.bitmap.iter()).enumerate() {
+                for offset in ArrayContainer::extract_set_bit_indices(lhs_bitmap & rhs_bitmap) {
+                    res.array.push((index * 64 + offset) as u16);
+                }
+            }
+            Self::ContainerTypeArray(res)
+        }
+    }
+
+    fn intersection_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> Self {
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::new(),
+        };
+        for value in lhs.array.iter() {
+            if rhs.contains(value) {
+                res.array.push(*value);
+            }
+        }
+        Self::ContainerTypeArray(res)
+    }
+
+    fn union_run_run(lhs: &RunContainer, rhs: &RunContainer) -> Self {
+        if lhs.is_full() {
+            return Self::ContainerTypeRun(lhs.clone());
+        }
+        if rhs.is_full() {
+            return Self::ContainerTypeRun(rhs.clone());
+        }
+        let mut res = RunContainer::new(lhs.most_significant_bits);
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.runs.len() || rhs_idx < rhs.runs.len() {
+            let (start, length) = if rhs_idx == rhs.runs.len()
+                || (lhs_idx < lhs.runs.len() && lhs.runs[lhs_idx].0 <= rhs.runs[rhs_idx].0)
+            {
+                lhs_idx += 1;
+                lhs.runs[lhs_idx - 1]
+            } else {
+                rhs_idx += 1;
+                rhs.runs[rhs_idx - 1]
+            };
+            res.push_run(start, start + length);
+        }
+        Self::from_run(res)
+    }
+
+    fn union_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> Self {
+        if rhs.is_full() {
+            return Self::ContainerTypeRun(rhs.clone());
+        }
+        let mut res = RunContainer::new(rhs.most_significant_bits);
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.array.len() || rhs_idx < rhs.runs.len() {
+            if rhs_idx == rhs.runs.len()
+                || (lhs_idx < lhs.array.len() && lhs.array[lhs_idx] <= rhs.runs[rhs_idx].0)
+            {
+                res.push_run(lhs.array[lhs_idx], lhs.array[lhs_idx]);
+                lhs_idx += 1;
+            } else {
+                let (start, length) = rhs.runs[rhs_idx];
+                res.push_run(start, start + length);
+                rhs_idx += 1;
+            }
+        }
+        Self::from_run(res)
+    }
+
+    fn union_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> Self {
+        if rhs.is_full() {
+            return Self::ContainerTypeRun(rhs.clone());
+        }
+        let mut res = lhs.clone();
+        Self::union_with_bitmap_run(&mut res, rhs);
+        Self::ContainerTypeBitmap(res)
+    }
+
+    fn intersection_run_run(lhs: &RunContainer, rhs: &RunContainer) -> Self {
+        let mut res = RunContainer::new(lhs.most_significant_bits);
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.runs.len() && rhs_idx < rhs.runs.len() {
+            let (lhs_start, lhs_length) = lhs.runs[lhs_idx];
+            let (rhs_start, rhs_length) = rhs.runs[rhs_idx];
+            let lhs_end = lhs_start + lhs_length;
+            let rhs_end = rhs_start + rhs_length;
+            let start = lhs_start.max(rhs_start);
+            let end = lhs_end.min(rhs_end);
+            if start <= end {
+                res.push_run(start, end);
+            }
+            if lhs_end < rhs_end {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        Self::from_run(res)
+    }
+
+    fn intersection_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> Self {
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::new(),
+        };
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.runs.len() {
+                break;
+            }
+            if rhs.runs[rhs_idx].0 <= value {
+                res.array.push(value);
+            }
+        }
+        Self::ContainerTypeArray(res)
+    }
+
+    fn intersection_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> Self {
+        if rhs.is_full() {
+            return Self::ContainerTypeBitmap(lhs.clone());
+        }
+        let mut res = BitmapContainer::new(lhs.most_significant_bits);
+        for &(start, length) in &rhs.runs {
+            for (index, mask) in BitmapContainer::range_masks(start, start + length) {
+                let intersection_bitmap = lhs.bitmap[index] & mask;
+                res.bitmap[index] |= intersection_bitmap;
+                res.cardinality += intersection_bitmap.count_ones() as usize;
+            }
+        }
+        Self::from_bitmap(res)
+    }
+}
+
+// Counting counterparts of the intersection kernels, for the `*_len` operations
+// that only need the size of a result and never build its container.
+impl ContainerType {
+    fn intersection_len_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> usize {
+        let (smaller_array, larger_array) = if lhs.array.len() > rhs.array.len() {
+            (&rhs.array, &lhs.array)
+        } else {
+            (&lhs.array, &rhs.array)
+        };
+        if ArrayContainer::is_skewed(smaller_array, larger_array) {
+            ArrayContainer::intersect_galloping_len(smaller_array, larger_array)
+        } else {
+            ArrayContainer::intersect_merge_len(smaller_array, larger_array)
+        }
+    }
+
+    fn intersection_len_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> usize {
+        lhs.bitmap.iter().zip(rhs.bitmap.iter()).map(|(lhs_bitmap, rhs_bitmap)| (lhs_bitmap & rhs_bitmap).count_ones() as usize).sum()
+    }
+
+    fn intersection_len_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> usize {
+        lhs.array.iter().filter(|value| rhs.contains(value)).count()
+    }
+
+    fn intersection_len_run_run(lhs: &RunContainer, rhs: &RunContainer) -> usize {
+        let mut res = 0;
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.runs.len() && rhs_idx < rhs.runs.len() {
+            let (lhs_start, lhs_length) = lhs.runs[lhs_idx];
+            let (rhs_start, rhs_length) = rhs.runs[rhs_idx];
+            let lhs_end = lhs_start + lhs_length;
+            let rhs_end = rhs_start + rhs_length;
+            let start = lhs_start.max(rhs_start);
+            let end = lhs_end.min(rhs_end);
+            if start <= end {
+                res += (end - start) as usize + 1;
+            }
+            if lhs_end < rhs_end {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        res
+    }
+
+    fn intersection_len_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> usize {
+        let mut res = 0;
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.runs.len() {
+                break;
+            }
+            res += (rhs.runs[rhs_idx].0 <= value) as usize;
+        }
+        res
+    }
+
+    fn intersection_len_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> usize {
+        if rhs.is_full() {
+            return lhs.cardinality;
+        }
+        rhs.runs
+            .iter()
+            .flat_map(|&(start, length)| BitmapContainer::range_masks(start, start + length))
+            .map(|(index, mask)| (lhs.bitmap[index] & mask).count_ones() as usize)
+            .sum()
+    }
+}
+
+// Predicates that return as soon as they meet a value deciding the answer, for
+// `is_subset` and `is_disjoint`.
+impl ContainerType {
+    fn is_subset_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> bool {
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            rhs_idx = ArrayContainer::advance_until(&rhs.array, rhs_idx, value);
+            if rhs_idx == rhs.array.len() || rhs.array[rhs_idx] != value {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn is_subset_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> bool {
+        lhs.bitmap.iter().zip(rhs.bitmap.iter()).all(|(lhs_bitmap, rhs_bitmap)| lhs_bitmap & !rhs_bitmap == 0)
+    }
+
+    fn is_subset_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> bool {
+        lhs.array.iter().all(|value| rhs.contains(value))
+    }
+
+    // Every run of `lhs` has to fit inside a single run of `rhs`, since runs never
+    // touch.
+    fn is_subset_run_run(lhs: &RunContainer, rhs: &RunContainer) -> bool {
+        let mut rhs_idx = 0;
+        for &(lhs_start, lhs_length) in &lhs.runs {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < lhs_start as u32 {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.runs.len() {
+                return false;
+            }
+            let (rhs_start, rhs_length) = rhs.runs[rhs_idx];
+            if rhs_start > lhs_start || rhs_start + rhs_length < lhs_start + lhs_length {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn is_subset_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> bool {
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.runs.len() || rhs.runs[rhs_idx].0 > value {
+                return false;
+            }
+        }
+        true
+    }
+
+    // The array is sorted and unique, so a run is covered when its first and last
+    // values sit exactly `length` positions apart in it.
+    fn is_subset_run_array(lhs: &RunContainer, rhs: &ArrayContainer) -> bool {
+        let mut rhs_idx = 0;
+        for &(start, length) in &lhs.runs {
+            rhs_idx = ArrayContainer::advance_until(&rhs.array, rhs_idx, start);
+            let end_idx = rhs_idx + length as usize;
+            if end_idx >= rhs.array.len() || rhs.array[rhs_idx] != start || rhs.array[end_idx] != start + length {
+                return false;
+            }
+            rhs_idx = end_idx + 1;
+        }
+        true
+    }
+
+    // Checks that `lhs` has no bit set in the gaps between the runs of `rhs`.
+    fn is_subset_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> bool {
+        let mut gap_start = 0;
+        for &(start, length) in &rhs.runs {
+            if start as u32 > gap_start && lhs.intersects_range(gap_start as u16, start - 1) {
+                return false;
+            }
+            gap_start = start as u32 + length as u32 + 1;
+        }
+        gap_start > u16::MAX as u32 || !lhs.intersects_range(gap_start as u16, u16::MAX)
+    }
+
+    fn is_subset_run_bitmap(lhs: &RunContainer, rhs: &BitmapContainer) -> bool {
+        lhs.runs.iter().all(|&(start, length)| {
+            BitmapContainer::range_masks(start, start + length).all(|(index, mask)| mask & !rhs.bitmap[index] == 0)
+        })
+    }
+
+    fn is_disjoint_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> bool {
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.array.len() && rhs_idx < rhs.array.len() {
+            let lhs_value = lhs.array[lhs_idx];
+            let rhs_value = rhs.array[rhs_idx];
+            if lhs_value == rhs_value {
+                return false;
+            } else if lhs_value < rhs_value {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        true
+    }
+
+    fn is_disjoint_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> bool {
+        lhs.bitmap.iter().zip(rhs.bitmap.iter()).all(|(lhs_bitmap, rhs_bitmap)| lhs_bitmap & rhs_bitmap == 0)
+    }
+
+    fn is_disjoint_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> bool {
+        !lhs.array.iter().any(|value| rhs.contains(value))
+    }
+
+    fn is_disjoint_run_run(lhs: &RunContainer, rhs: &RunContainer) -> bool {
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.runs.len() && rhs_idx < rhs.runs.len() {
+            let (lhs_start, lhs_length) = lhs.runs[lhs_idx];
+            let (rhs_start, rhs_length) = rhs.runs[rhs_idx];
+            let lhs_end = lhs_start + lhs_length;
+            let rhs_end = rhs_start + rhs_length;
+            if lhs_start.max(rhs_start) <= lhs_end.min(rhs_end) {
+                return false;
+            }
+            if lhs_end < rhs_end {
+                lhs_idx += 1;
+            } else {
+                rhs_idx += 1;
+            }
+        }
+        true
+    }
+
+    fn is_disjoint_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> bool {
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.runs.len() {
+                break;
+            }
+            if rhs.runs[rhs_idx].0 <= value {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn is_disjoint_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> bool {
+        !rhs.runs.iter().any(|&(start, length)| lhs.intersects_range(start, start + length))
+    }
+}
+
+impl ContainerType {
+    fn difference_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> Self {
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::with_capacity(lhs.array.len()),
+        };
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            while rhs_idx < rhs.array.len() && rhs.array[rhs_idx] < value {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.array.len() || rhs.array[rhs_idx] != value {
+                res.array.push(value);
+            }
+        }
+        Self::ContainerTypeArray(res)
+    }
+
+    fn difference_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> Self {
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::with_capacity(lhs.array.len()),
+        };
+        for value in lhs.array.iter() {
+            if !rhs.contains(value) {
+                res.array.push(*value);
+            }
+        }
+        Self::ContainerTypeArray(res)
+    }
+
+    fn difference_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> Self {
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::with_capacity(lhs.array.len()),
+        };
+        let mut rhs_idx = 0;
+        for &value in &lhs.array {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
+                rhs_idx += 1;
+            }
+            if rhs_idx == rhs.runs.len() || rhs.runs[rhs_idx].0 > value {
+                res.array.push(value);
             }
         }
-        ArrayContainer {
-            most_significant_bits: bitmap_container.most_significant_bits,
-            array,
+        Self::ContainerTypeArray(res)
+    }
+
+    fn difference_bitmap_array(lhs: &BitmapContainer, rhs: &ArrayContainer) -> Self {
+        let mut res = lhs.clone();
+        Self::difference_with_bitmap_array(&mut res, rhs);
+        Self::from_bitmap(res)
+    }
+
+    fn difference_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> Self {
+        let bitmap = Box::new(std::array::from_fn(|index| lhs.bitmap[index] & !rhs.bitmap[index]));
+        Self::from_bitmap(BitmapContainer::from_words(lhs.most_significant_bits, bitmap))
+    }
+
+    fn difference_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> Self {
+        let mut res = lhs.clone();
+        Self::difference_with_bitmap_run(&mut res, rhs);
+        Self::from_bitmap(res)
+    }
+
+    fn difference_run_array(lhs: &RunContainer, rhs: &ArrayContainer) -> Self {
+        let mut res = RunContainer::new(lhs.most_significant_bits);
+        let mut rhs_idx = 0;
+        for &(start, length) in &lhs.runs {
+            let end = start as u32 + length as u32;
+            let mut next_start = start as u32;
+            while rhs_idx < rhs.array.len() && rhs.array[rhs_idx] as u32 <= end {
+                let value = rhs.array[rhs_idx] as u32;
+                if value > next_start {
+                    res.push_run(next_start as u16, (value - 1) as u16);
+                }
+                next_start = next_start.max(value + 1);
+                rhs_idx += 1;
+            }
+            if next_start <= end {
+                res.push_run(next_start as u16, end as u16);
+            }
         }
+        Self::from_run(res)
     }
-}
 
-impl ArrayContainer {
-    fn extract_set_bit_indices(mut bitmap: u64) -> Vec<usize> {
-        let mut indices = Vec::with_capacity(bitmap.count_ones() as usize);
-        while bitmap != 0 {
-            let lsb_value = bitmap & (!bitmap + 1);
-            let lsb_bit_index = lsb_value.trailing_zeros() as usize;
-            indices.push(lsb_bit_index);
-            bitmap &= bitmap - 1;
+    fn difference_run_bitmap(lhs: &RunContainer, rhs: &BitmapContainer) -> Self {
+        let mut res = BitmapContainer::from(lhs.clone());
+        for (res_bitmap, rhs_bitmap) in res.bitmap.iter_mut().zip(rhs.bitmap.iter()) {
+            *res_bitmap &= !rhs_bitmap;
         }
-        indices
+        res.cardinality = res.count_set_bits();
+        Self::from_bitmap(res)
     }
-}
 
-impl Clone for ArrayContainer {
-    fn clone(&self) -> Self {
-        ArrayContainer {
-            most_significant_bits: self.most_significant_bits,
-            array: self.array.clone(),
+    fn difference_run_run(lhs: &RunContainer, rhs: &RunContainer) -> Self {
+        let mut res = RunContainer::new(lhs.most_significant_bits);
+        let mut rhs_idx = 0;
+        for &(start, length) in &lhs.runs {
+            let end = start as u32 + length as u32;
+            let mut next_start = start as u32;
+            while rhs_idx < rhs.runs.len() && (rhs.runs[rhs_idx].0 as u32) <= end {
+                let (rhs_start, rhs_length) = rhs.runs[rhs_idx];
+                let rhs_end = rhs_start as u32 + rhs_length as u32;
+                if rhs_start as u32 > next_start {
+                    res.push_run(next_start as u16, rhs_start - 1);
+                }
+                next_start = next_start.max(rhs_end + 1);
+                if rhs_end > end {
+                    // This run of rhs may still cover the next run of lhs.
+                    break;
+                }
+                rhs_idx += 1;
+            }
+            if next_start <= end {
+                res.push_run(next_start as u16, end as u16);
+            }
         }
+        Self::from_run(res)
     }
-}
 
-#[derive(Debug)]
-struct BitmapContainer {
-    most_significant_bits: u16,
-    cardinality: usize,
-    bitmap: Vec<u64>,
-}
+    fn symmetric_difference_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> Self {
+        let mut res = ArrayContainer {
+            most_significant_bits: lhs.most_significant_bits,
+            array: Vec::with_capacity(lhs.array.len() + rhs.array.len()),
+        };
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx < lhs.array.len() && rhs_idx < rhs.array.len() {
+            let lhs_value = lhs.array[lhs_idx];
+            let rhs_value = rhs.array[rhs_idx];
+            if lhs_value == rhs_value {
+                lhs_idx += 1;
+                rhs_idx += 1;
+            } else if lhs_value < rhs_value {
+                res.array.push(lhs_value);
+                lhs_idx += 1;
+            } else {
+                res.array.push(rhs_value);
+                rhs_idx += 1;
+            }
+        }
+        res.array.extend_from_slice(&lhs.array[lhs_idx..]);
+        res.array.extend_from_slice(&rhs.array[rhs_idx..]);
+        if res.array.len() > 4096 {
+            Self::ContainerTypeBitmap(BitmapContainer::from(res))
+        } else {
+            Self::ContainerTypeArray(res)
+        }
+    }
 
-impl BitmapContainer {
-    fn add(&mut self, value: u16) {
-        let (index, offset) = Self::get_index_and_offset(&value);
-        self.bitmap.resize(index + 1, 0);
-        let before_bitwise_op = self.bitmap[index];
-        self.bitmap[index] |= 1 << offset;
-        let after_bitwise_op = self.bitmap[index];
-        self.cardinality += (before_bitwise_op != after_bitwise_op) as usize;
+    fn symmetric_difference_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> Self {
+        let mut res = rhs.clone();
+        Self::symmetric_difference_with_bitmap_array(&mut res, lhs);
+        Self::from_bitmap(res)
     }
 
-    fn contains(&self, value: &u16) -> bool {
-        let (index, offset) = Self::get_index_and_offset(value);
-        if index >= self.bitmap.len() {
-            return false;
-        }
-        self.bitmap[index] & (1 << offset) != 0
+    fn symmetric_difference_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> Self {
+        let bitmap = Box::new(std::array::from_fn(|index| lhs.bitmap[index] ^ rhs.bitmap[index]));
+        Self::from_bitmap(BitmapContainer::from_words(lhs.most_significant_bits, bitmap))
     }
 
-    fn remove(&mut self, value: &u16) {
-        let (index, offset) = Self::get_index_and_offset(value);
-        let before_bitwise_op = self.bitmap[index];
-        self.bitmap[index] &= !(1 << offset);
-        let after_bitwise_op = self.bitmap[index];
-        self.cardinality -= (before_bitwise_op != after_bitwise_op) as usize;
+    fn symmetric_difference_bitmap_run(lhs: &BitmapContainer, rhs: &RunContainer) -> Self {
+        let mut res = lhs.clone();
+        Self::symmetric_difference_with_bitmap_run(&mut res, rhs);
+        Self::from_bitmap(res)
     }
 
-    fn get_index_and_offset(value: &u16) -> (usize, usize) {
-        (*value as usize / 64, *value as usize % 64)
+    fn symmetric_difference_array_run(lhs: &ArrayContainer, rhs: &RunContainer) -> Self {
+        let lhs_boundaries = lhs.array.iter().flat_map(|&value| [value as u32, value as u32 + 1]);
+        let rhs_boundaries = rhs.runs.iter().flat_map(|&(start, length)| [start as u32, start as u32 + length as u32 + 1]);
+        Self::from_run(RunContainer::from_boundaries(rhs.most_significant_bits, lhs_boundaries, rhs_boundaries))
+    }
+
+    fn symmetric_difference_run_run(lhs: &RunContainer, rhs: &RunContainer) -> Self {
+        let lhs_boundaries = lhs.runs.iter().flat_map(|&(start, length)| [start as u32, start as u32 + length as u32 + 1]);
+        let rhs_boundaries = rhs.runs.iter().flat_map(|&(start, length)| [start as u32, start as u32 + length as u32 + 1]);
+        Self::from_run(RunContainer::from_boundaries(lhs.most_significant_bits, lhs_boundaries, rhs_boundaries))
     }
 }
 
-impl From<ArrayContainer> for BitmapContainer {
-    fn from(array_container: ArrayContainer) -> Self {
-        let mut bitmap = vec![0; 1 + array_container.array.last().map_or(0, |&x| x as usize / 64)];
-        for &value in &array_container.array {
-            let (index, offset) = BitmapContainer::get_index_and_offset(&value);
-            bitmap[index] |= 1 << offset;
+// In-place kernels that update the storage of `lhs` instead of allocating a new
+// container. Callers are responsible for downgrading bitmaps afterwards.
+impl ContainerType {
+    fn union_with_bitmap_array(lhs: &mut BitmapContainer, rhs: &ArrayContainer) {
+        for value in rhs.array.iter() {
+            let (index, offset) = BitmapContainer::get_index_and_offset(value);
+            lhs.cardinality += ((!lhs.bitmap[index] >> offset) & 1) as usize;
+            lhs.bitmap[index] |= 1 << offset;
         }
-        BitmapContainer {
-            most_significant_bits: array_container.most_significant_bits,
-            bitmap,
-            cardinality: array_container.array.len(),
+    }
+
+    fn union_with_bitmap_bitmap(lhs: &mut BitmapContainer, rhs: &BitmapContainer) {
+        for (lhs_bitmap, rhs_bitmap) in lhs.bitmap.iter_mut().zip(rhs.bitmap.iter()) {
+            *lhs_bitmap |= rhs_bitmap;
         }
+        lhs.cardinality = lhs.count_set_bits();
     }
-}
 
-impl Clone for BitmapContainer {
-    fn clone(&self) -> Self {
-        BitmapContainer {
-            most_significant_bits: self.most_significant_bits,
-            cardinality: self.cardinality,
-            bitmap: self.bitmap.clone(),
+    fn union_with_bitmap_run(lhs: &mut BitmapContainer, rhs: &RunContainer) {
+        for &(start, length) in &rhs.runs {
+            lhs.add_range(start, start + length);
         }
     }
-}
 
-impl ContainerType {
-    fn union_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> Self {
-        let sum = lhs.array.len() + rhs.array.len();
-        if sum > 4096 {
-            let mut res = BitmapContainer {
-                most_significant_bits: lhs.most_significant_bits,
-                cardinality: 0,
-                bitmap: Vec::with_capacity(1 + sum / 64),
-            };
-            let mut lhs_idx = 0;
-            let mut rhs_idx = 0;
-            while lhs_idx < lhs.array.len() && rhs_idx < rhs.array.len() {
-                let lhs_value = lhs.array[lhs_idx];
-                let rhs_value = rhs.array[rhs_idx];
-                if lhs_value == rhs_value {
-                    res.add(lhs_value);
-                    lhs_idx += 1;
-                    rhs_idx += 1;
-                } else if lhs_value < rhs_value {
-                    res.add(lhs_value);
-                    lhs_idx += 1;
-                } else {
-                    res.add(rhs_value);
-                    rhs_idx += 1;
-                }
-            }
-            while lhs_idx < lhs.array.len() {
-                res.add(lhs.array[lhs_idx]);
-                lhs_idx += 1;
-            }
-            while rhs_idx < rhs.array.len() {
-                res.add(rhs.array[rhs_idx]);
-                rhs_idx += 1;
-            }
-            if res.cardinality > 4096 {
-                Self::ContainerTypeBitmap(res)
+    fn intersect_with_array_array(lhs: &mut ArrayContainer, rhs: &ArrayContainer) {
+        if ArrayContainer::is_skewed(&rhs.array, &lhs.array) {
+            let mut array = Vec::with_capacity(rhs.array.len());
+            ArrayContainer::intersect_galloping(&rhs.array, &lhs.array, &mut array);
+            lhs.array = array;
+            return;
+        }
+        let skewed = ArrayContainer::is_skewed(&lhs.array, &rhs.array);
+        let mut rhs_idx = 0;
+        lhs.array.retain(|&value| {
+            if skewed {
+                rhs_idx = ArrayContainer::advance_until(&rhs.array, rhs_idx, value);
             } else {
-                Self::ContainerTypeArray(ArrayContainer::from(res))
-            }
-        } else {
-            let mut res = ArrayContainer {
-                most_significant_bits: lhs.most_significant_bits,
-                array: Vec::with_capacity(sum),
-            };
-            let mut lhs_idx = 0;
-            let mut rhs_idx = 0;
-            while lhs_idx < lhs.array.len() && rhs_idx < rhs.array.len() {
-                let lhs_value = lhs.array[lhs_idx];
-                let rhs_value = rhs.array[rhs_idx];
-                if lhs_value == rhs_value {
-                    res.array.push(lhs_value);
-                    lhs_idx += 1;
-                    rhs_idx += 1;
-                } else if lhs_value < rhs_value {
-                    res.array.push(lhs_value);
-                    lhs_idx += 1;
-                } else {
-                    res.array.push(rhs_value);
+                while rhs_idx < rhs.array.len() && rhs.array[rhs_idx] < value {
                     rhs_idx += 1;
                 }
             }
-            while lhs_idx < lhs.array.len() {
-                res.array.push(lhs.array[lhs_idx]);
-                lhs_idx += 1;
-            }
-            while rhs_idx < rhs.array.len() {
-                res.array.push(rhs.array[rhs_idx]);
+            rhs_idx < rhs.array.len() && rhs.array[rhs_idx] == value
+        });
+    }
+
+    fn intersect_with_array_run(lhs: &mut ArrayContainer, rhs: &RunContainer) {
+        let mut rhs_idx = 0;
+        lhs.array.retain(|&value| {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
                 rhs_idx += 1;
             }
-            Self::ContainerTypeArray(res)
-        }
+            rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 <= value
+        });
     }
 
-    fn union_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> Self {
-        const SELECT: bool = true;
-        if SELECT {
-            Self::approach_paper(lhs, rhs)
-        } else {
-            Self::approach_custom(lhs, rhs)
+    fn intersect_with_bitmap_bitmap(lhs: &mut BitmapContainer, rhs: &BitmapContainer) {
+        for (lhs_bitmap, rhs_bitmap) in lhs.bitmap.iter_mut().zip(rhs.bitmap.iter()) {
+            *lhs_bitmap &= rhs_bitmap;
         }
+        lhs.cardinality = lhs.count_set_bits();
     }
 
-    /*
-    This is an Alternate approach wherein we take a clone of the larger of lhs and rhs then iterate over the smaller of lhs and rhs
-    and add the values to the clone (don't use the add function but do bitwise OR). This approach is more efficient because it avoids resizing the bitmap vector.
-    Also to get the correct value of cardinality, we just need to compare the change in set bits for each u64 value,
-    before and after the bitwise OR operations.
-     */
-    fn approach_custom(lhs: &BitmapContainer, rhs: &BitmapContainer) -> ContainerType {
-        let mut res = max_by_key(lhs, rhs, |&container| container.bitmap.len()).clone();
-        let smaller = min_by_key(lhs, rhs, |&container| container.bitmap.len()).clone();
-        for (index, rhs_bitmap) in smaller.bitmap.iter().enumerate() {
-            let original_set_bits_count = res.bitmap[index].count_ones();
-            res.bitmap[index] |= rhs_bitmap;
-            let modified_set_bits_count = res.bitmap[index].count_ones();
-            res.cardinality += (modified_set_bits_count - original_set_bits_count) as usize;
+    // Clears the gaps before, between and after the runs of rhs.
+    fn intersect_with_bitmap_run(lhs: &mut BitmapContainer, rhs: &RunContainer) {
+        let mut gap_start = 0u32;
+        for &(start, length) in &rhs.runs {
+            if start as u32 > gap_start {
+                lhs.remove_range(gap_start as u16, start - 1);
+            }
+            gap_start = start as u32 + length as u32 + 1;
+        }
+        if gap_start <= u16::MAX as u32 {
+            lhs.remove_range(gap_start as u16, u16::MAX);
         }
-        Self::ContainerTypeBitmap(res)
     }
 
-    fn approach_paper(lhs: &BitmapContainer, rhs: &BitmapContainer) -> ContainerType {
-        let mut res = BitmapContainer {
-            most_significant_bits: lhs.most_significant_bits,
-            cardinality: 0,
-            bitmap: Vec::with_capacity(max(lhs.bitmap.len(), rhs.bitmap.len())),
-        };
-        let mut lhs_idx = 0;
+    fn difference_with_array_array(lhs: &mut ArrayContainer, rhs: &ArrayContainer) {
         let mut rhs_idx = 0;
-        while lhs_idx < lhs.bitmap.len() && rhs_idx < rhs.bitmap.len() {
-            let lhs_bitmap = lhs.bitmap[lhs_idx];
-            let rhs_bitmap = rhs.bitmap[rhs_idx];
-            let union_bitmap = lhs_bitmap | rhs_bitmap;
-            res.bitmap.push(union_bitmap);
-            res.cardinality += union_bitmap.count_ones() as usize;
-            lhs_idx += 1;
-            rhs_idx += 1;
-        }
-        while lhs_idx < lhs.bitmap.len() {
-            let lhs_bitmap = lhs.bitmap[lhs_idx];
-            res.bitmap.push(lhs_bitmap);
-            res.cardinality += lhs_bitmap.count_ones() as usize;
-            lhs_idx += 1;
+        lhs.array.retain(|&value| {
+            while rhs_idx < rhs.array.len() && rhs.array[rhs_idx] < value {
+                rhs_idx += 1;
+            }
+            rhs_idx == rhs.array.len() || rhs.array[rhs_idx] != value
+        });
+    }
+
+    fn difference_with_array_run(lhs: &mut ArrayContainer, rhs: &RunContainer) {
+        let mut rhs_idx = 0;
+        lhs.array.retain(|&value| {
+            while rhs_idx < rhs.runs.len() && rhs.runs[rhs_idx].0 as u32 + (rhs.runs[rhs_idx].1 as u32) < value as u32 {
+                rhs_idx += 1;
+            }
+            rhs_idx == rhs.runs.len() || rhs.runs[rhs_idx].0 > value
+        });
+    }
+
+    fn difference_with_bitmap_array(lhs: &mut BitmapContainer, rhs: &ArrayContainer) {
+        for value in rhs.array.iter() {
+            let (index, offset) = BitmapContainer::get_index_and_offset(value);
+            lhs.cardinality -= ((lhs.bitmap[index] >> offset) & 1) as usize;
+            lhs.bitmap[index] &= !(1 << offset);
         }
-        while rhs_idx < rhs.bitmap.len() {
-            let rhs_bitmap = rhs.bitmap[rhs_idx];
-            res.bitmap.push(rhs_bitmap);
-            res.cardinality += rhs_bitmap.count_ones() as usize;
-            rhs_idx += 1;
+    }
+
+    fn difference_with_bitmap_bitmap(lhs: &mut BitmapContainer, rhs: &BitmapContainer) {
+        for (lhs_bitmap, rhs_bitmap) in lhs.bitmap.iter_mut().zip(rhs.bitmap.iter()) {
+            *lhs_bitmap &= !rhs_bitmap;
         }
-        Self::ContainerTypeBitmap(res)
+        lhs.cardinality = lhs.count_set_bits();
     }
 
-    fn union_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> Self {
-        let mut res = rhs.clone();
-        for value in lhs.array.iter() {
-            res.add(*value);
+    fn difference_with_bitmap_run(lhs: &mut BitmapContainer, rhs: &RunContainer) {
+        for &(start, length) in &rhs.runs {
+            lhs.remove_range(start, start + length);
         }
-        Self::ContainerTypeBitmap(res)
     }
 
-    // TODO: Use Galloping intersections
-    fn intersection_array_array(lhs: &ArrayContainer, rhs: &ArrayContainer) -> Self {
-        let mut res = ArrayContainer {
-            most_significant_bits: lhs.most_significant_bits,
-            array: Vec::new(),
-        };
-        let (smaller_array, larger_array) = if lhs.array.len() > rhs.array.len() {
-            (rhs, lhs)
-        } else {
-            (lhs, rhs)
-        };
-        for value in smaller_array.array.iter() {
-            if larger_array.contains(value) {
-                res.array.push(*value);
+    fn symmetric_difference_with_bitmap_array(lhs: &mut BitmapContainer, rhs: &ArrayContainer) {
+        for value in rhs.array.iter() {
+            let (index, offset) = BitmapContainer::get_index_and_offset(value);
+            lhs.bitmap[index] ^= 1 << offset;
+            if lhs.bitmap[index] & (1 << offset) != 0 {
+                lhs.cardinality += 1;
+            } else {
+                lhs.cardinality -= 1;
             }
         }
-        Self::ContainerTypeArray(res)
     }
 
-    fn intersection_bitmap_bitmap(lhs: &BitmapContainer, rhs: &BitmapContainer) -> Self {
-        let mut cardinality = 0;
-        let mut idx = 0;
-        while idx < lhs.bitmap.len() && idx < rhs.bitmap.len() {
-            let lhs_bitmap = lhs.bitmap[idx];
-            let rhs_bitmap = rhs.bitmap[idx];
-            let union_bitmap = lhs_bitmap & rhs_bitmap;
-            cardinality += union_bitmap.count_ones() as usize;
-            idx += 1;
+    fn symmetric_difference_with_bitmap_bitmap(lhs: &mut BitmapContainer, rhs: &BitmapContainer) {
+        for (lhs_bitmap, rhs_bitmap) in lhs.bitmap.iter_mut().zip(rhs.bitmap.iter()) {
+            *lhs_bitmap ^= rhs_bitmap;
         }
+        lhs.cardinality = lhs.count_set_bits();
+    }
 
-        if cardinality > 4096 {
-            let mut res = BitmapContainer {
-                most_significant_bits: lhs.most_significant_bits,
-                cardinality,
-                bitmap: Vec::with_capacity(max(lhs.bitmap.len(), rhs.bitmap.len())),
-            };
-            let mut idx = 0;
-            while idx < lhs.bitmap.len() && idx < rhs.bitmap.len() {
-                let lhs_bitmap = lhs.bitmap[idx];
-                let rhs_bitmap = rhs.bitmap[idx];
-                let intersection_bitmap = lhs_bitmap & rhs_bitmap;
-                res.bitmap.push(intersection_bitmap);
-                idx += 1;
-            }
-            Self::ContainerTypeBitmap(res)
-        } else {
-            let mut res = ArrayContainer {
-                most_significant_bits: lhs.most_significant_bits,
-                array: Vec::with_capacity(cardinality),
-            };
-            let mut idx = 0;
-            while idx < lhs.bitmap.len() && idx < rhs.bitmap.len() {
-                let lhs_bitmap = lhs.bitmap[idx];
-                let rhs_bitmap = rhs.bitmap[idx];
-                let intersection_bitmap = lhs_bitmap & rhs_bitmap;
-                let indices = ArrayContainer::extract_set_bit_indices(intersection_bitmap);
-                for offset in indices {
-                    res.array.push((idx * 64 + offset) as u16);
-                }
-                idx += 1;
-            }
-            Self::ContainerTypeArray(res)
+    fn symmetric_difference_with_bitmap_run(lhs: &mut BitmapContainer, rhs: &RunContainer) {
+        for &(start, length) in &rhs.runs {
+            lhs.flip_range(start, start + length);
         }
     }
+}
 
-    fn intersection_array_bitmap(lhs: &ArrayContainer, rhs: &BitmapContainer) -> Self {
-        let mut res = ArrayContainer {
-            most_significant_bits: lhs.most_significant_bits,
-            array: Vec::new(),
-        };
-        for value in lhs.array.iter() {
-            if rhs.contains(value) {
-                res.array.push(*value);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn build(values: &BTreeSet<u32>) -> RoaringBitmap {
+        let mut bitmap = RoaringBitmap::new();
+        for &value in values {
+            bitmap.add(value);
+        }
+        bitmap.run_optimize();
+        bitmap
+    }
+
+    // Checks the count kept by every container against the values it decodes to.
+    fn assert_cardinalities(bitmap: &RoaringBitmap) {
+        for container in &bitmap.data {
+            let mut cursor = ContainerCursor::new(&container.container);
+            let mut count = 0;
+            while cursor.next(&container.container).is_some() {
+                count += 1;
             }
+            assert_eq!(container.container.cardinality(), count);
         }
-        Self::ContainerTypeArray(res)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn assert_matches(bitmap: &RoaringBitmap, expected: &BTreeSet<u32>) {
+        assert_cardinalities(bitmap);
+        assert_eq!(bitmap.len(), expected.len() as u64);
+        assert!(bitmap.iter().eq(expected.iter().copied()));
+        for &value in expected {
+            assert!(bitmap.contains(value), "value {value}");
+            if let Some(next) = value.checked_add(1) {
+                assert_eq!(bitmap.contains(next), expected.contains(&next), "value {next}");
+            }
+        }
+    }
+
+    fn container_kind(bitmap: &RoaringBitmap) -> &'static str {
+        match bitmap.data[0].container {
+            ContainerType::ContainerTypeArray(_) => "array",
+            ContainerType::ContainerTypeBitmap(_) => "bitmap",
+            ContainerType::ContainerTypeRun(_) => "run",
+        }
+    }
 
     #[test]
     fn it_works() {
@@ -601,17 +3254,95 @@ mod tests {
             b.add(i);
         }
         for i in 0..5000 {
-            assert_eq!(a.contains(i), true);
+            assert!(a.contains(i));
         }
         for i in 5000..10_000 {
-            assert_eq!(a.contains(i), false);
+            assert!(!a.contains(i));
         }
         for i in 0..5000 {
             a.remove(i);
         }
         for i in 0..5000 {
-            assert_eq!(a.contains(i), false);
+            assert!(!a.contains(i));
+        }
+        assert!(a.is_empty());
+        assert_eq!(a.len(), 0);
+    }
+
+    #[test]
+    fn bitmap_container_keeps_high_bits_when_adding_lower_values() {
+        // The container turns into a bitmap halfway through, and every later value
+        // lands in a lower word than the ones already set.
+        let mut a = RoaringBitmap::new();
+        for i in (0..5000).rev() {
+            a.add(i * 13);
+        }
+        assert!(matches!(a.data[0].container, ContainerType::ContainerTypeBitmap(_)));
+        assert_eq!(a.len(), 5000);
+        assert!(a.iter().eq((0..5000).map(|i| i * 13)));
+        assert_eq!(a.max(), Some(4999 * 13));
+        assert_cardinalities(&a);
+    }
+
+    #[test]
+    fn bitmap_container_removes_values_past_its_highest_value() {
+        let mut a: RoaringBitmap = (0..5000).collect();
+        assert!(matches!(a.data[0].container, ContainerType::ContainerTypeBitmap(_)));
+        a.remove(65_535);
+        a.remove(60_000);
+        assert_eq!(a.len(), 5000);
+        assert!(!a.contains(65_535));
+
+        let b: RoaringBitmap = [65_535].into_iter().collect();
+        a.difference_with(&b);
+        a.symmetric_difference_with(&b);
+        assert!(a.contains(65_535));
+        a.intersect_with(&(0..10).collect());
+        assert!(a.iter().eq(0..10));
+    }
+
+    #[test]
+    fn removing_every_value_drops_containers() {
+        let mut a = RoaringBitmap::new();
+        for i in (0..20_000).step_by(2).chain((100_000..100_020).step_by(2)).chain(200_000..210_000) {
+            a.add(i);
+        }
+        a.run_optimize();
+        assert!(matches!(a.data[0].container, ContainerType::ContainerTypeBitmap(_)));
+        assert!(matches!(a.data[1].container, ContainerType::ContainerTypeArray(_)));
+        assert!(matches!(a.data[2].container, ContainerType::ContainerTypeRun(_)));
+
+        for i in (100_000..100_020).step_by(2) {
+            a.remove(i);
+        }
+        assert_eq!(a.data.len(), 2);
+        for i in 200_000..210_000 {
+            a.remove(i);
         }
+        assert_eq!(a.data.len(), 1);
+        for i in (0..20_000).step_by(2) {
+            a.remove(i);
+        }
+        assert!(a.is_empty());
+        assert!(a.data.is_empty());
+        assert_eq!(a.iter().next(), None);
+        assert_eq!(a.min(), None);
+
+        let mut bytes = Vec::new();
+        a.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(a.serialized_size(), 8);
+
+        let b: RoaringBitmap = [7, 300_000].into_iter().collect();
+        let mut c = a.union(&b);
+        assert_eq!(c.data.len(), 2);
+        c.union_with(&a);
+        assert_eq!(c.data.len(), 2);
+        c.remove(7);
+        c.remove(300_000);
+        c.remove(300_000);
+        assert!(c.is_empty());
+        assert!(a.union(&a).data.is_empty());
     }
 
     #[test]
@@ -627,7 +3358,7 @@ mod tests {
 
         let c = a.union(&b);
         for i in 0..10_000 {
-            assert_eq!(c.contains(i), true);
+            assert!(c.contains(i));
         }
     }
 
@@ -638,19 +3369,19 @@ mod tests {
         for i in 0..5000 {
             a.add(i);
         }
-        for i in 5_00_000..10_00_000 {
+        for i in 500_000..1_000_000 {
             b.add(i);
         }
 
         let c = a.union(&b);
         for i in 0..5000 {
-            assert_eq!(c.contains(i), true);
+            assert!(c.contains(i));
         }
-        for i in 5000..5_00_000 {
-            assert_eq!(c.contains(i), false);
+        for i in 5000..500_000 {
+            assert!(!c.contains(i));
         }
-        for i in 5_00_000..10_00_000 {
-            assert_eq!(c.contains(i), true);
+        for i in 500_000..1_000_000 {
+            assert!(c.contains(i));
         }
     }
 
@@ -667,13 +3398,13 @@ mod tests {
 
         let c = a.intersection(&b);
         for i in 0..2500 {
-            assert_eq!(c.contains(i), false);
+            assert!(!c.contains(i));
         }
         for i in 2500..5000 {
-            assert_eq!(c.contains(i), true);
+            assert!(c.contains(i));
         }
         for i in 5000..7500 {
-            assert_eq!(c.contains(i), false);
+            assert!(!c.contains(i));
         }
     }
 
@@ -685,11 +3416,726 @@ mod tests {
         for i in 0..5000 {
             a.add(i);
         }
-        for  i in 10_00_000..15_00_000 {
+        for i in 1_000_000..1_500_000 {
             b.add(i);
         }
 
         let c = a.intersection(&b);
-        assert_eq!(c.is_empty(), true);
+        assert!(c.is_empty());
+    }
+
+    #[test]
+    fn len_counts_values_across_containers() {
+        let mut a = RoaringBitmap::default();
+        assert_eq!(a.len(), 0);
+        for i in 0..5000 {
+            a.add(i);
+        }
+        for i in 1_000_000..1_000_010 {
+            a.add(i);
+        }
+        a.add(4999);
+        assert_eq!(a.len(), 5010);
+        a.remove(1_000_000);
+        assert_eq!(a.len(), 5009);
+    }
+
+    #[test]
+    fn clear_empties_bitmap() {
+        let mut a = RoaringBitmap::with_capacity(4);
+        for i in 0..200_000 {
+            a.add(i);
+        }
+        assert!(!a.is_empty());
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.len(), 0);
+        assert!(!a.contains(100));
+        a.add(100);
+        assert!(a.contains(100));
+    }
+
+    #[test]
+    fn run_optimize_converts_dense_ranges() {
+        let mut a = RoaringBitmap::new();
+        for i in 0..5000 {
+            a.add(i);
+        }
+        assert_eq!(container_kind(&a), "bitmap");
+        assert!(a.run_optimize());
+        assert_eq!(container_kind(&a), "run");
+        assert_eq!(a.len(), 5000);
+        assert!(a.contains(0));
+        assert!(a.contains(4999));
+        assert!(!a.contains(5000));
+
+        let mut b = RoaringBitmap::new();
+        for i in (0..3000).step_by(3) {
+            b.add(i);
+        }
+        assert!(!b.run_optimize());
+        assert_eq!(container_kind(&b), "array");
+    }
+
+    #[test]
+    fn run_optimize_converts_fragmented_runs_back() {
+        let mut a = RoaringBitmap::new();
+        for i in 0..100 {
+            a.add(i);
+        }
+        assert!(a.run_optimize());
+        for i in (200..1000).step_by(2) {
+            a.add(i);
+        }
+        assert_eq!(container_kind(&a), "run");
+        assert!(!a.run_optimize());
+        assert_eq!(container_kind(&a), "array");
+        assert_eq!(a.len(), 500);
+    }
+
+    #[test]
+    fn run_container_add_and_remove() {
+        let mut expected: BTreeSet<u32> = (100..200).chain(300..400).chain(65530..65536).collect();
+        let mut a = build(&expected);
+        assert_eq!(container_kind(&a), "run");
+
+        for value in [99, 200, 250, 299, 65535, 65529, 0] {
+            a.add(value);
+            expected.insert(value);
+        }
+        for value in 201..250 {
+            a.add(value);
+            expected.insert(value);
+        }
+        assert_eq!(container_kind(&a), "run");
+        assert_matches(&a, &expected);
+
+        for value in [150, 99, 399, 0, 65535, 65529, 65533, 500] {
+            a.remove(value);
+            expected.remove(&value);
+        }
+        assert_eq!(container_kind(&a), "run");
+        assert_matches(&a, &expected);
+    }
+
+    // Pairs of expected values and bitmaps, covering every container type.
+    fn container_inputs() -> Vec<(BTreeSet<u32>, RoaringBitmap)> {
+        let arrays: Vec<BTreeSet<u32>> = vec![
+            (0..65536).step_by(23).collect(),
+            (3..65536).step_by(17).collect(),
+        ];
+        let bitmaps: Vec<BTreeSet<u32>> = vec![
+            (10_000..30_000).filter(|v| v % 3 != 0).collect(),
+            (0..65536).filter(|v| v % 5 != 0).collect(),
+        ];
+        let runs: Vec<BTreeSet<u32>> = vec![
+            (0..3000).chain(5000..9000).chain(20_000..25_000).chain(60_000..65536).collect(),
+            (2500..5500).chain(24_000..40_000).chain(65535..65536).collect(),
+            (0..65536).collect(),
+        ];
+        let mut inputs = Vec::new();
+        for (kind, sets) in [("array", arrays), ("bitmap", bitmaps), ("run", runs)] {
+            for set in sets {
+                let bitmap = build(&set);
+                assert_eq!(container_kind(&bitmap), kind);
+                inputs.push((set, bitmap));
+            }
+        }
+        inputs
+    }
+
+    // The bitmaps of `container_inputs`, followed by one spanning several keys and an
+    // empty one, for the operations that combine many bitmaps.
+    fn many_inputs() -> Vec<RoaringBitmap> {
+        let mut bitmaps: Vec<RoaringBitmap> = container_inputs().into_iter().map(|(_, bitmap)| bitmap).collect();
+        let mut shifted = RoaringBitmap::new();
+        shifted.insert_range(60_000..200_000);
+        shifted.add(5_000_000);
+        bitmaps.push(shifted);
+        bitmaps.push(RoaringBitmap::new());
+        bitmaps
+    }
+
+    // Every operation against the same operation on a `BTreeSet`, over every pair of
+    // container types. The tests of single operations only cover their edge cases.
+    #[test]
+    fn operations_match_btreeset() {
+        let inputs = container_inputs();
+        for (lhs_set, lhs) in &inputs {
+            for (rhs_set, rhs) in &inputs {
+                let expected: [BTreeSet<u32>; 4] = [
+                    lhs_set.union(rhs_set).copied().collect(),
+                    lhs_set.intersection(rhs_set).copied().collect(),
+                    lhs_set.difference(rhs_set).copied().collect(),
+                    lhs_set.symmetric_difference(rhs_set).copied().collect(),
+                ];
+                let mut in_place = [lhs.clone(), lhs.clone(), lhs.clone(), lhs.clone()];
+                in_place[0].union_with(rhs);
+                in_place[1].intersect_with(rhs);
+                in_place[2].difference_with(rhs);
+                in_place[3].symmetric_difference_with(rhs);
+                let allocating = [lhs.union(rhs), lhs.intersection(rhs), lhs.difference(rhs), lhs.symmetric_difference(rhs)];
+                let operators = [
+                    [lhs | rhs, lhs.clone() | rhs],
+                    [lhs & rhs, lhs.clone() & rhs],
+                    [lhs - rhs, lhs.clone() - rhs],
+                    [lhs ^ rhs, lhs.clone() ^ rhs],
+                ];
+                let counts = [lhs.union_len(rhs), lhs.intersection_len(rhs), lhs.difference_len(rhs), lhs.symmetric_difference_len(rhs)];
+                for (index, expected) in expected.iter().enumerate() {
+                    assert_matches(&allocating[index], expected);
+                    assert_matches(&in_place[index], expected);
+                    for result in &operators[index] {
+                        assert_cardinalities(result);
+                        assert_eq!(result.len(), expected.len() as u64);
+                    }
+                    assert_eq!(counts[index], expected.len() as u64);
+                }
+                assert_eq!(lhs.is_subset(rhs), lhs_set.is_subset(rhs_set));
+                assert_eq!(lhs.is_superset(rhs), lhs_set.is_superset(rhs_set));
+                assert_eq!(lhs.is_disjoint(rhs), lhs_set.is_disjoint(rhs_set));
+            }
+        }
+
+        let mut random = 0x853C_49E6_748F_EA9Bu64;
+        let mut next = move || {
+            random ^= random << 13;
+            random ^= random >> 7;
+            random ^= random << 17;
+            random
+        };
+        for (set, bitmap) in &inputs {
+            let mut expected = set.clone();
+            let mut a = bitmap.clone();
+            assert_eq!(a.insert_range(1000..=70_000), (1000..=70_000).filter(|value| expected.insert(*value)).count() as u64);
+            assert_matches(&a, &expected);
+
+            let mut expected = set.clone();
+            let mut a = bitmap.clone();
+            assert_eq!(a.insert_range(4000..4100), (4000..4100).filter(|value| expected.insert(*value)).count() as u64);
+            assert_matches(&a, &expected);
+
+            let mut expected = set.clone();
+            let mut a = bitmap.clone();
+            assert_eq!(a.remove_range(1000..=30_000), (1000..=30_000).filter(|value| expected.remove(value)).count() as u64);
+            assert_matches(&a, &expected);
+
+            for (start, end) in [(1000, 70_000), (4000, 4100), (0, 65535), (65530, 65545)] {
+                let mut expected = set.clone();
+                for value in start..=end {
+                    if !expected.remove(&value) {
+                        expected.insert(value);
+                    }
+                }
+                let mut a = bitmap.clone();
+                a.flip_range(start..=end);
+                assert_matches(&a, &expected);
+                a.flip_range(start..=end);
+                assert_matches(&a, set);
+
+                let expected: BTreeSet<u32> = (start..=end).filter(|value| !set.contains(value)).collect();
+                assert_matches(&bitmap.complement_within(start..=end), &expected);
+            }
+
+            let batch: Vec<u32> = (0..20_000).map(|_| (next() % 200_000) as u32).chain([5, 5, u32::MAX]).collect();
+            let mut expected = set.clone();
+            let added = batch.iter().filter(|&&value| expected.insert(value)).count() as u64;
+            let mut a = bitmap.clone();
+            assert_eq!(a.add_many(&batch), added);
+            assert_matches(&a, &expected);
+            assert_eq!(a.add_many(&batch), 0);
+        }
+    }
+
+    #[test]
+    fn difference_drops_empty_containers() {
+        let mut a = RoaringBitmap::new();
+        let mut b = RoaringBitmap::new();
+        for i in 0..5000 {
+            a.add(i);
+            b.add(i);
+        }
+        for i in 1_000_000..1_000_100 {
+            a.add(i);
+        }
+        b.add(2_000_000);
+
+        let c = a.difference(&b);
+        assert_eq!(c.data.len(), 1);
+        assert_eq!(c.len(), 100);
+        assert!(c.contains(1_000_000));
+        assert!(!c.contains(0));
+
+        let d = a.symmetric_difference(&b);
+        assert_eq!(d.data.len(), 2);
+        assert_eq!(d.len(), 101);
+        assert!(d.contains(2_000_000));
+        assert!(a.difference(&a).is_empty());
+        assert!(a.symmetric_difference(&a).is_empty());
+    }
+
+    #[test]
+    fn difference_downgrades_to_array() {
+        let mut a = RoaringBitmap::new();
+        let mut b = RoaringBitmap::new();
+        for i in 0..10_000 {
+            a.add(i);
+        }
+        for i in 0..6000 {
+            b.add(i);
+        }
+        let c = a.difference(&b);
+        assert_eq!(container_kind(&c), "array");
+        assert_eq!(c.len(), 4000);
+        let d = a.symmetric_difference(&b);
+        assert_eq!(container_kind(&d), "array");
+        assert_eq!(d.len(), 4000);
+    }
+
+    #[test]
+    fn counting_operations_across_containers() {
+        let mut a = RoaringBitmap::new();
+        let mut b = RoaringBitmap::new();
+        a.insert_range(0..5000);
+        a.insert_range(1_000_000..1_000_100);
+        b.insert_range(1_000_000..1_000_100);
+        b.insert_range(500_000..500_010);
+        b.add(2_000_000);
+        assert_eq!(a.intersection_len(&b), 100);
+        assert_eq!(a.union_len(&b), 5111);
+        assert_eq!(a.difference_len(&b), 5000);
+        assert_eq!(b.difference_len(&a), 11);
+        assert_eq!(a.symmetric_difference_len(&b), 5011);
+        assert_eq!(a.intersection_len(&RoaringBitmap::new()), 0);
+        assert_eq!(RoaringBitmap::new().union_len(&a), a.len());
+    }
+
+    #[test]
+    fn jaccard_index() {
+        let mut a = RoaringBitmap::new();
+        let mut b = RoaringBitmap::new();
+        a.insert_range(0..300);
+        b.insert_range(100..400);
+        b.add(1_000_000);
+        assert_eq!(a.jaccard_index(&b), 200.0 / 401.0);
+        assert_eq!(a.jaccard_index(&a), 1.0);
+        assert_eq!(a.jaccard_index(&RoaringBitmap::new()), 0.0);
+        assert!(RoaringBitmap::new().jaccard_index(&RoaringBitmap::new()).is_nan());
+    }
+
+    #[test]
+    fn subset_superset_and_disjoint() {
+        let inputs = container_inputs();
+        let mut derived = Vec::new();
+        for (set, bitmap) in &inputs {
+            for subset in [
+                set.iter().copied().filter(|value| value % 7 != 0).collect::<BTreeSet<u32>>(),
+                set.range(20_000..21_000).copied().collect(),
+                set.range(..40_000).copied().collect(),
+            ] {
+                let rest: BTreeSet<u32> = set.difference(&subset).copied().collect();
+                derived.push((build(&subset), bitmap, build(&rest)));
+            }
+        }
+        for (subset, set, rest) in &derived {
+            assert!(subset.is_subset(set));
+            assert!(set.is_superset(subset));
+            assert_eq!(set.is_subset(subset), set.len() == subset.len());
+            assert!(subset.is_disjoint(rest) && rest.is_disjoint(subset));
+            assert_eq!(set.is_disjoint(subset), subset.is_empty());
+        }
+
+        let run = build(&(100..200).collect());
+        let array = build(&(100..200).chain((1000..60_000).step_by(20)).collect());
+        assert_eq!((container_kind(&run), container_kind(&array)), ("run", "array"));
+        assert!(run.is_subset(&array));
+        assert!(!build(&(100..201).collect()).is_subset(&array));
+        assert!(!build(&(99..200).collect()).is_subset(&array));
+
+        let mut a = RoaringBitmap::new();
+        a.insert_range(0..10);
+        a.insert_range(70_000..70_010);
+        let mut b = RoaringBitmap::new();
+        b.insert_range(0..100);
+        assert!(!a.is_subset(&b) && !b.is_subset(&a));
+        assert!(!a.is_disjoint(&b));
+        b.insert_range(70_000..70_010);
+        assert!(a.is_subset(&b) && b.is_superset(&a));
+        assert!(RoaringBitmap::new().is_subset(&a));
+        assert!(RoaringBitmap::new().is_disjoint(&a));
+        assert!(!a.is_subset(&RoaringBitmap::new()));
+    }
+
+    #[test]
+    fn union_many_and_intersection_many_match_folds() {
+        let bitmaps = many_inputs();
+
+        for count in 0..=bitmaps.len() {
+            let inputs = &bitmaps[..count];
+            let union = RoaringBitmap::union_many(inputs);
+            let folded = inputs.iter().fold(RoaringBitmap::new(), |res, bitmap| res.union(bitmap));
+            assert!(union.iter().eq(folded.iter()));
+            assert_cardinalities(&union);
+
+            let intersection = RoaringBitmap::intersection_many(inputs);
+            let expected: BTreeSet<u32> = match inputs.split_first() {
+                Some((first, rest)) => first.iter().filter(|&value| rest.iter().all(|bitmap| bitmap.contains(value))).collect(),
+                None => BTreeSet::new(),
+            };
+            assert_matches(&intersection, &expected);
+        }
+
+        let full = RoaringBitmap::union_many(&bitmaps[..7]);
+        assert_eq!(container_kind(&full), "run");
+        assert_eq!(full.len(), 65536);
+        assert!(RoaringBitmap::intersection_many([&bitmaps[0], &bitmaps[7], &bitmaps[8]]).is_empty());
+    }
+
+    #[test]
+    fn at_least_matches_per_value_counts() {
+        let bitmaps = many_inputs();
+
+        let candidates = RoaringBitmap::union_many(&bitmaps);
+        for k in 1..=bitmaps.len() + 1 {
+            let expected: BTreeSet<u32> = candidates.iter().filter(|&value| bitmaps.iter().filter(|bitmap| bitmap.contains(value)).count() >= k).collect();
+            assert_matches(&RoaringBitmap::at_least(&bitmaps, k), &expected);
+        }
+
+        let majority: BTreeSet<u32> = candidates.iter().filter(|&value| bitmaps.iter().filter(|bitmap| bitmap.contains(value)).count() > bitmaps.len() / 2).collect();
+        assert_matches(&RoaringBitmap::majority(&bitmaps), &majority);
+
+        let all = RoaringBitmap::at_least(&bitmaps, 0);
+        assert_eq!(all.len(), 1 << 32);
+        assert!(all.contains(0) && all.contains(u32::MAX));
+        assert_eq!(RoaringBitmap::at_least([], 0).len(), 1 << 32);
+        assert!(RoaringBitmap::at_least([], 1).is_empty());
+        assert!(RoaringBitmap::majority([]).is_empty());
+    }
+
+    #[test]
+    fn add_many_across_containers() {
+        let mut a = RoaringBitmap::new();
+        assert_eq!(a.add_many(&[]), 0);
+        assert_eq!(a.add_many(&(0..5000).rev().collect::<Vec<u32>>()), 5000);
+        assert_eq!(container_kind(&a), "bitmap");
+        assert_eq!(a.add_many(&[300_000, 7, 100_000]), 2);
+        assert!(a.iter().eq((0..5000).chain([100_000, 300_000])));
+    }
+
+    #[test]
+    fn in_place_operations_across_containers() {
+        let mut a = RoaringBitmap::new();
+        let mut b = RoaringBitmap::new();
+        for i in 0..5000 {
+            a.add(i);
+        }
+        for i in 1_000_000..1_000_100 {
+            a.add(i);
+            b.add(i);
+        }
+        for i in 500_000..500_010 {
+            b.add(i);
+        }
+        b.add(2_000_000);
+
+        let mut c = a.clone();
+        c.union_with(&b);
+        assert_eq!(c.data.len(), 4);
+        assert_eq!(c.len(), 5111);
+        assert!(c.contains(500_000) && c.contains(2_000_000) && c.contains(4999));
+
+        let mut c = a.clone();
+        c.intersect_with(&b);
+        assert_eq!(c.data.len(), 1);
+        assert_eq!(c.len(), 100);
+
+        let mut c = a.clone();
+        c.difference_with(&b);
+        assert_eq!(c.data.len(), 1);
+        assert_eq!(c.len(), 5000);
+
+        let mut c = a.clone();
+        c.symmetric_difference_with(&b);
+        assert_eq!(c.data.len(), 3);
+        assert_eq!(c.len(), 5011);
+        assert!(!c.contains(1_000_000));
+    }
+
+    #[test]
+    fn set_operators() {
+        let mut a = RoaringBitmap::new();
+        let mut b = RoaringBitmap::new();
+        let mut c = RoaringBitmap::new();
+        for i in 0..5000 {
+            a.add(i);
+        }
+        for i in 2500..7500 {
+            b.add(i);
+        }
+        for i in 4000..1_000_000 {
+            c.add(i);
+        }
+
+        assert_eq!((&a | &b).len(), a.union(&b).len());
+        assert_eq!((&a & &b).len(), a.intersection(&b).len());
+        assert_eq!((&a - &b).len(), a.difference(&b).len());
+        assert_eq!((&a ^ &b).len(), a.symmetric_difference(&b).len());
+
+        let expected = a.union(&b.intersection(&c));
+        let d = &a | &(&b & &c);
+        assert_eq!(d.len(), expected.len());
+        assert_eq!((a.clone() | (&b & c.clone())).len(), expected.len());
+
+        assert_eq!((a.clone() - &b).len(), 2500);
+        assert_eq!((&a - b.clone()).len(), 2500);
+        assert_eq!((a.clone() - b.clone()).len(), 2500);
+        assert_eq!((a.clone() ^ &b).len(), 5000);
+        assert_eq!((&a ^ b.clone()).len(), 5000);
+        assert_eq!((a.clone() & b.clone()).len(), 2500);
+
+        let mut e = a.clone();
+        e |= &b;
+        assert_eq!(e.len(), 7500);
+        e &= &c;
+        assert_eq!(e.len(), 3500);
+        e -= b.clone();
+        assert!(e.is_empty());
+        e ^= a;
+        assert_eq!(e.len(), 5000);
+    }
+
+    #[test]
+    fn iter_yields_values_in_ascending_order() {
+        let mut expected: BTreeSet<u32> = (0..100).chain(70_000..80_000).chain(200_000..200_010).collect();
+        expected.extend((500_000..600_000).step_by(7));
+        expected.extend([u32::MAX - 1, u32::MAX]);
+        let mut a = build(&expected);
+        assert!(a.run_optimize());
+        assert!(a.iter().eq(expected.iter().copied()));
+
+        let mut values = Vec::new();
+        for value in &a {
+            values.push(value);
+        }
+        assert!(values.iter().copied().eq(expected.iter().copied()));
+
+        a.remove(70_000);
+        expected.remove(&70_000);
+        assert!(a.into_iter().eq(expected.into_iter()));
+        assert_eq!(RoaringBitmap::new().iter().next(), None);
+    }
+
+    #[test]
+    fn from_iter_and_extend() {
+        let mut a: RoaringBitmap = [5, 1, 70_000, 3, 1].into_iter().collect();
+        assert_eq!(a.len(), 4);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![1, 3, 5, 70_000]);
+
+        a.extend(10..20);
+        assert_eq!(a.len(), 14);
+        assert!(a.iter().eq([1, 3, 5].into_iter().chain(10..20).chain([70_000])));
+    }
+
+    #[test]
+    fn len_tracks_run_container_updates() {
+        let mut expected: BTreeSet<u32> = (0..1000).chain(2000..3000).collect();
+        let mut a = build(&expected);
+        assert_eq!(container_kind(&a), "run");
+        for value in [999, 1000, 1001, 1999, 500, 500, 0, 65535] {
+            a.add(value);
+            expected.insert(value);
+            assert_matches(&a, &expected);
+        }
+        for value in [1000, 1000, 2500, 65535, 0, 4000] {
+            a.remove(value);
+            expected.remove(&value);
+            assert_matches(&a, &expected);
+        }
+    }
+
+    #[test]
+    fn array_intersection_strategies_agree() {
+        let larger: Vec<u16> = (0..4000).map(|i| i * 16 + i % 7).collect();
+        let smaller_inputs: Vec<Vec<u16>> = vec![
+            Vec::new(),
+            vec![0],
+            vec![u16::MAX],
+            vec![larger[0], larger[1], larger[3999]],
+            (0..10).map(|i| larger[i * 397]).collect(),
+            (0..10).map(|i| larger[i * 397] + 1).collect(),
+            (0..10).map(|i| i * 6000).collect(),
+            (0..60).map(|i| larger[i * 50 + 3]).chain([63_999, 64_000]).collect(),
+        ];
+        for smaller in smaller_inputs {
+            let expected: Vec<u16> = smaller.iter().copied().filter(|value| larger.binary_search(value).is_ok()).collect();
+            let mut merged = Vec::new();
+            ArrayContainer::intersect_merge(&smaller, &larger, &mut merged);
+            assert_eq!(merged, expected);
+            let mut galloped = Vec::new();
+            ArrayContainer::intersect_galloping(&smaller, &larger, &mut galloped);
+            assert_eq!(galloped, expected);
+            assert_eq!(ArrayContainer::intersect_merge_len(&smaller, &larger), expected.len());
+            assert_eq!(ArrayContainer::intersect_galloping_len(&smaller, &larger), expected.len());
+
+            let lhs: RoaringBitmap = smaller.iter().map(|&value| value as u32).collect();
+            let rhs: RoaringBitmap = larger.iter().map(|&value| value as u32).collect();
+            assert!(lhs.intersection(&rhs).iter().eq(expected.iter().map(|&value| value as u32)));
+            assert!(rhs.intersection(&lhs).iter().eq(expected.iter().map(|&value| value as u32)));
+            let mut in_place = lhs.clone();
+            in_place.intersect_with(&rhs);
+            assert!(in_place.iter().eq(expected.iter().map(|&value| value as u32)));
+            let mut in_place = rhs.clone();
+            in_place.intersect_with(&lhs);
+            assert!(in_place.iter().eq(expected.iter().map(|&value| value as u32)));
+            assert_eq!(lhs.intersection_len(&rhs), expected.len() as u64);
+            assert_eq!(rhs.intersection_len(&lhs), expected.len() as u64);
+        }
+    }
+
+    #[test]
+    fn advance_until_finds_first_value_not_below() {
+        let array: Vec<u16> = (0..100).map(|i| i * 3).collect();
+        for start in [0, 1, 17, 99, 100] {
+            for value in [0, 1, 3, 50, 51, 297, 298, u16::MAX] {
+                let expected = start.max(array.partition_point(|&x| x < value));
+                assert_eq!(ArrayContainer::advance_until(&array, start, value), expected, "start {start}, value {value}");
+            }
+        }
+        assert_eq!(ArrayContainer::advance_until(&[], 0, 5), 0);
+    }
+
+    #[test]
+    fn rank_and_select() {
+        let mut inputs = container_inputs();
+        let multi: BTreeSet<u32> = (0..5000).chain(70_000..70_100).chain(500_000..600_000).chain([u32::MAX]).collect();
+        inputs.push((multi.clone(), build(&multi)));
+        inputs.push((BTreeSet::new(), RoaringBitmap::new()));
+        for (set, bitmap) in &inputs {
+            let values: Vec<u32> = set.iter().copied().collect();
+            for (n, &value) in values.iter().enumerate().step_by(37) {
+                assert_eq!(bitmap.select(n as u64), Some(value));
+                assert_eq!(bitmap.rank(value), n as u64 + 1);
+                if value > 0 {
+                    assert_eq!(bitmap.rank(value - 1), n as u64);
+                }
+            }
+            assert_eq!(bitmap.select(values.len() as u64), None);
+            if let Some(&last) = values.last() {
+                assert_eq!(bitmap.select(values.len() as u64 - 1), Some(last));
+            }
+            assert_eq!(bitmap.rank(u32::MAX), values.len() as u64);
+        }
+
+        let a = build(&multi);
+        assert_eq!(a.rank(6000), 5000);
+        assert_eq!(a.rank(69_999), 5000);
+        assert_eq!(a.rank(70_050), 5051);
+        assert_eq!(a.select(5100), Some(500_000));
+    }
+
+    #[test]
+    fn min_max_successor_predecessor() {
+        let mut inputs = container_inputs();
+        let multi: BTreeSet<u32> = (0..5000).chain(70_000..70_100).chain(500_000..600_000).chain([u32::MAX]).collect();
+        inputs.push((multi.clone(), build(&multi)));
+        for (set, bitmap) in &inputs {
+            assert_eq!(bitmap.min(), set.first().copied());
+            assert_eq!(bitmap.max(), set.last().copied());
+            for value in (0..700_000).step_by(97).chain([0, 1, 65535, 65536, u32::MAX - 1, u32::MAX]) {
+                let expected_successor = value.checked_add(1).and_then(|next| set.range(next..).next().copied());
+                assert_eq!(bitmap.successor(value), expected_successor);
+                assert_eq!(bitmap.predecessor(value), set.range(..value).next_back().copied());
+            }
+        }
+
+        let empty = RoaringBitmap::new();
+        assert_eq!(empty.min(), None);
+        assert_eq!(empty.max(), None);
+        assert_eq!(empty.successor(5), None);
+        assert_eq!(empty.predecessor(5), None);
+
+        let a = build(&multi);
+        assert_eq!(a.next_after(4999), Some(70_000));
+        assert_eq!(a.prev_before(70_000), Some(4999));
+        assert_eq!(a.next_after(600_000), Some(u32::MAX));
+        assert_eq!(a.next_after(u32::MAX), None);
+        assert_eq!(a.prev_before(0), None);
+    }
+
+    #[test]
+    fn insert_range_fills_containers() {
+        let mut a = RoaringBitmap::new();
+        assert_eq!(a.insert_range(10..1_000_000), 999_990);
+        assert_eq!(a.len(), 999_990);
+        assert_eq!(a.data.len(), 16);
+        assert!(a.data[1..15].iter().all(|container| matches!(container.container, ContainerType::ContainerTypeRun(_))));
+        assert_eq!(a.min(), Some(10));
+        assert_eq!(a.max(), Some(999_999));
+        assert!(!a.contains(9) && !a.contains(1_000_000));
+        assert_eq!(a.rank(500_000), 499_991);
+
+        assert_eq!(a.insert_range(5..=20), 5);
+        assert_eq!(a.insert_range((Bound::Included(20), Bound::Excluded(10))), 0);
+        assert_eq!(a.insert_range((Bound::Excluded(u32::MAX), Bound::Unbounded)), 0);
+        assert_eq!(a.insert_range(..), u32::MAX as u64 + 1 - 999_995);
+        assert_eq!(a.len(), u32::MAX as u64 + 1);
+        assert_eq!(a.data.len(), 65536);
+        assert!(a.contains(u32::MAX));
+
+        let mut b = RoaringBitmap::new();
+        b.insert_range(u32::MAX - 2..=u32::MAX);
+        assert_eq!(b.iter().collect::<Vec<_>>(), vec![u32::MAX - 2, u32::MAX - 1, u32::MAX]);
+    }
+
+    #[test]
+    fn remove_range_drops_containers() {
+        let mut a = RoaringBitmap::new();
+        a.insert_range(0..300_000);
+        for i in (400_000..500_000).step_by(3) {
+            a.add(i);
+        }
+        a.add(u32::MAX);
+
+        assert_eq!(a.remove_range(100..=200_000), 199_901);
+        assert_eq!(a.data.len(), 6);
+        assert_eq!(a.remove_range(250_000..), 50_000 + 33_334 + 1);
+        assert_eq!(a.len(), 100 + 50_000 - 1);
+        assert_eq!(a.max(), Some(249_999));
+        assert_eq!(a.remove_range(..), 100 + 50_000 - 1);
+        assert!(a.is_empty());
+        assert_eq!(a.remove_range(5..5), 0);
+    }
+
+    #[test]
+    fn flip_range_creates_and_removes_containers() {
+        let mut a = RoaringBitmap::new();
+        a.insert_range(65536..131_072);
+        a.add(10);
+        a.flip_range(..=200_000);
+        assert_eq!(a.data.len(), 3);
+        assert_eq!(a.len(), 65536 - 1 + 200_001 - 131_072);
+        assert!(!a.contains(10) && a.contains(11) && !a.contains(65536) && a.contains(131_072));
+
+        a.flip_range(u32::MAX..);
+        assert_eq!(a.max(), Some(u32::MAX));
+        a.flip_range(u32::MAX..);
+        assert_eq!(a.max(), Some(200_000));
+        a.flip_range(..=200_000);
+        assert_eq!(a.data.len(), 2);
+        assert_eq!(a.len(), 65537);
+        assert!(a.iter().eq([10].into_iter().chain(65536..131_072)));
+    }
+
+    #[test]
+    fn complement_within_range() {
+        let mut a = RoaringBitmap::new();
+        a.insert_range(0..1000);
+        a.insert_range(500_000..600_000);
+        let b = a.complement_within(..1_000_000);
+        assert_eq!(b.len(), 1_000_000 - 1000 - 100_000);
+        assert!(b.intersection(&a).is_empty());
+        assert_eq!(b.union(&a).len(), 1_000_000);
+        assert!(a.complement_within(0..1000).is_empty());
+        assert!(a.complement_within(0..1000).data.is_empty());
     }
 }
@@ -0,0 +1,398 @@
+// Reading and writing bitmaps in the portable Roaring format shared by the Java, C
+// and Go implementations: https://github.com/RoaringBitmap/RoaringFormatSpec
+use std::io::{self, Read, Write};
+
+use crate::{ArrayContainer, BitmapContainer, Container, ContainerType, RoaringBitmap, RunContainer};
+
+pub(crate) const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+pub(crate) const SERIAL_COOKIE: u16 = 12347;
+// With run containers present, bitmaps with fewer containers than this skip the
+// offset header.
+pub(crate) const NO_OFFSET_THRESHOLD: usize = 4;
+
+impl RoaringBitmap {
+    /// Writes the bitmap to `writer` in the portable Roaring format.
+    pub fn serialize_into<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let has_run_containers = self.has_run_containers();
+        if has_run_containers {
+            let cookie = SERIAL_COOKIE as u32 | ((self.data.len() as u32 - 1) << 16);
+            writer.write_all(&cookie.to_le_bytes())?;
+            let mut run_flags = vec![0u8; self.data.len().div_ceil(8)];
+            for (index, container) in self.data.iter().enumerate() {
+                if matches!(container.container, ContainerType::ContainerTypeRun(_)) {
+                    run_flags[index / 8] |= 1 << (index % 8);
+                }
+            }
+            writer.write_all(&run_flags)?;
+        } else {
+            writer.write_all(&SERIAL_COOKIE_NO_RUNCONTAINER.to_le_bytes())?;
+            writer.write_all(&(self.data.len() as u32).to_le_bytes())?;
+        }
+
+        for container in &self.data {
+            writer.write_all(&container.most_significant_bits.to_le_bytes())?;
+            writer.write_all(&((container.container.cardinality() - 1) as u16).to_le_bytes())?;
+        }
+
+        if !has_run_containers || self.data.len() >= NO_OFFSET_THRESHOLD {
+            let mut offset = Self::header_size(self.data.len(), has_run_containers);
+            for container in &self.data {
+                writer.write_all(&(offset as u32).to_le_bytes())?;
+                offset += container.container.serialized_size();
+            }
+        }
+
+        for container in &self.data {
+            container.container.serialize_into(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a bitmap written in the portable Roaring format from `reader`.
+    pub fn deserialize_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let cookie = read_u32(&mut reader)?;
+        let (size, run_flags) = if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+            (read_u32(&mut reader)? as usize, None)
+        } else if cookie as u16 == SERIAL_COOKIE {
+            let size = (cookie >> 16) as usize + 1;
+            let mut run_flags = vec![0u8; size.div_ceil(8)];
+            reader.read_exact(&mut run_flags)?;
+            (size, Some(run_flags))
+        } else {
+            return Err(invalid_data("unknown cookie"));
+        };
+        if size > 1 << 16 {
+            return Err(invalid_data("more than 2^16 containers"));
+        }
+
+        let mut descriptions = Vec::with_capacity(size);
+        for _ in 0..size {
+            let most_significant_bits = read_u16(&mut reader)?;
+            let cardinality = read_u16(&mut reader)? as usize + 1;
+            if descriptions.last().is_some_and(|&(previous, _)| previous >= most_significant_bits) {
+                return Err(invalid_data("container keys are not strictly increasing"));
+            }
+            descriptions.push((most_significant_bits, cardinality));
+        }
+
+        // Containers are stored back to back, so the offsets are not needed when
+        // reading the whole stream.
+        if run_flags.is_none() || size >= NO_OFFSET_THRESHOLD {
+            let offsets_size = 4 * size as u64;
+            if io::copy(&mut reader.by_ref().take(offsets_size), &mut io::sink())? != offsets_size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+
+        let mut data = Vec::with_capacity(size);
+        for (index, (most_significant_bits, cardinality)) in descriptions.into_iter().enumerate() {
+            let is_run = run_flags.as_ref().is_some_and(|run_flags| run_flags[index / 8] & (1 << (index % 8)) != 0);
+            let container = if is_run {
+                ContainerType::ContainerTypeRun(RunContainer::deserialize_from(&mut reader, most_significant_bits)?)
+            } else if cardinality <= 4096 {
+                ContainerType::ContainerTypeArray(ArrayContainer::deserialize_from(&mut reader, most_significant_bits, cardinality)?)
+            } else {
+                ContainerType::ContainerTypeBitmap(BitmapContainer::deserialize_from(&mut reader, most_significant_bits)?)
+            };
+            if container.cardinality() != cardinality {
+                return Err(invalid_data("container cardinality does not match its header"));
+            }
+            data.push(Container { most_significant_bits, container });
+        }
+        Ok(RoaringBitmap { data })
+    }
+
+    /// Returns the number of bytes [`RoaringBitmap::serialize_into`] writes.
+    pub fn serialized_size(&self) -> usize {
+        let has_run_containers = self.has_run_containers();
+        let mut size = Self::header_size(self.data.len(), has_run_containers);
+        for container in &self.data {
+            size += container.container.serialized_size();
+        }
+        size
+    }
+
+    fn has_run_containers(&self) -> bool {
+        self.data.iter().any(|container| matches!(container.container, ContainerType::ContainerTypeRun(_)))
+    }
+
+    // Bytes before the first container: cookie, run flags or container count,
+    // key/cardinality pairs and, when present, the offsets.
+    pub(crate) fn header_size(size: usize, has_run_containers: bool) -> usize {
+        if has_run_containers {
+            let offsets_size = if size >= NO_OFFSET_THRESHOLD { 4 * size } else { 0 };
+            4 + size.div_ceil(8) + 4 * size + offsets_size
+        } else {
+            8 + 4 * size + 4 * size
+        }
+    }
+}
+
+impl ContainerType {
+    // Non-run containers are written according to their cardinality, which is what
+    // readers use to tell arrays and bitmaps apart.
+    fn serialized_size(&self) -> usize {
+        match self {
+            Self::ContainerTypeRun(run_container) => RunContainer::size_in_bytes(run_container.runs.len()),
+            container if container.cardinality() <= 4096 => ArrayContainer::size_in_bytes(container.cardinality()),
+            _ => BitmapContainer::SIZE_IN_BYTES,
+        }
+    }
+
+    fn serialize_into<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(self.serialized_size());
+        match self {
+            Self::ContainerTypeRun(run_container) => {
+                buffer.extend_from_slice(&(run_container.runs.len() as u16).to_le_bytes());
+                for &(start, length) in &run_container.runs {
+                    buffer.extend_from_slice(&start.to_le_bytes());
+                    buffer.extend_from_slice(&length.to_le_bytes());
+                }
+            }
+            Self::ContainerTypeArray(array_container) if array_container.array.len() <= 4096 => {
+                for value in &array_container.array {
+                    buffer.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Self::ContainerTypeBitmap(bitmap_container) if bitmap_container.cardinality > 4096 => {
+                for bitmap in bitmap_container.bitmap.iter() {
+                    buffer.extend_from_slice(&bitmap.to_le_bytes());
+                }
+            }
+            container => {
+                let mut bitmap = vec![0u64; BitmapContainer::SIZE_IN_BYTES / 8];
+                let mut cursor = crate::ContainerCursor::new(container);
+                while let Some(value) = cursor.next(container) {
+                    if container.cardinality() <= 4096 {
+                        buffer.extend_from_slice(&value.to_le_bytes());
+                    } else {
+                        bitmap[value as usize / 64] |= 1 << (value % 64);
+                    }
+                }
+                if container.cardinality() > 4096 {
+                    for word in bitmap {
+                        buffer.extend_from_slice(&word.to_le_bytes());
+                    }
+                }
+            }
+        }
+        writer.write_all(&buffer)
+    }
+}
+
+impl ArrayContainer {
+    fn deserialize_from<R: Read>(reader: &mut R, most_significant_bits: u16, cardinality: usize) -> io::Result<Self> {
+        let mut bytes = vec![0u8; 2 * cardinality];
+        reader.read_exact(&mut bytes)?;
+        let array: Vec<u16> = bytes.chunks_exact(2).map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]])).collect();
+        if array.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(invalid_data("array container values are not strictly increasing"));
+        }
+        Ok(ArrayContainer { most_significant_bits, array })
+    }
+}
+
+impl BitmapContainer {
+    fn deserialize_from<R: Read>(reader: &mut R, most_significant_bits: u16) -> io::Result<Self> {
+        let mut bytes = vec![0u8; Self::SIZE_IN_BYTES];
+        reader.read_exact(&mut bytes)?;
+        let mut bitmap = Box::new([0u64; 1024]);
+        for (bitmap, chunk) in bitmap.iter_mut().zip(bytes.chunks_exact(8)) {
+            *bitmap = u64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes long"));
+        }
+        Ok(BitmapContainer::from_words(most_significant_bits, bitmap))
+    }
+}
+
+impl RunContainer {
+    fn deserialize_from<R: Read>(reader: &mut R, most_significant_bits: u16) -> io::Result<Self> {
+        let number_of_runs = read_u16(reader)? as usize;
+        let mut bytes = vec![0u8; 4 * number_of_runs];
+        reader.read_exact(&mut bytes)?;
+        let mut run_container = RunContainer::new(most_significant_bits);
+        let mut previous_end: Option<u32> = None;
+        for chunk in bytes.chunks_exact(4) {
+            let start = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let length = u16::from_le_bytes([chunk[2], chunk[3]]);
+            let end = start as u32 + length as u32;
+            // Touching runs would be merged, and the bitmap would then no longer
+            // serialize back to the same bytes.
+            if end > u16::MAX as u32 || previous_end.is_some_and(|previous_end| start as u32 <= previous_end + 1) {
+                return Err(invalid_data("run container runs overlap, touch or overflow"));
+            }
+            run_container.push_run(start, end as u16);
+            previous_end = Some(end);
+        }
+        Ok(run_container)
+    }
+}
+
+pub(crate) fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Reads `bytes` from `start` on. Like any reader it fails with `UnexpectedEof` once
+// it runs out, which here includes a `start` past the end.
+pub(crate) fn reader_at(bytes: &[u8], start: usize) -> &[u8] {
+    bytes.get(start..).unwrap_or_default()
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bitmap: &RoaringBitmap) -> RoaringBitmap {
+        let mut bytes = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), bitmap.serialized_size());
+        let deserialized = RoaringBitmap::deserialize_from(&bytes[..]).unwrap();
+        let mut reserialized = Vec::new();
+        deserialized.serialize_into(&mut reserialized).unwrap();
+        assert_eq!(bytes, reserialized);
+        deserialized
+    }
+
+    #[test]
+    fn array_containers_match_spec() {
+        let bitmap: RoaringBitmap = [1, 2, 3, 100_000].into_iter().collect();
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x3A, 0x30, 0x00, 0x00, // cookie 12346
+            0x02, 0x00, 0x00, 0x00, // 2 containers
+            0x00, 0x00, 0x02, 0x00, // key 0, cardinality 3
+            0x01, 0x00, 0x00, 0x00, // key 1, cardinality 1
+            0x18, 0x00, 0x00, 0x00, // offset 24
+            0x1E, 0x00, 0x00, 0x00, // offset 30
+            0x01, 0x00, 0x02, 0x00, 0x03, 0x00,
+            0xA0, 0x86, // 100_000 - 65_536
+        ];
+        let mut bytes = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        assert!(RoaringBitmap::deserialize_from(&expected[..]).unwrap().iter().eq([1, 2, 3, 100_000]));
+    }
+
+    #[test]
+    fn run_containers_without_offsets_match_spec() {
+        let mut bitmap: RoaringBitmap = (0..10).chain([2 * 65536 + 5]).collect();
+        assert!(bitmap.run_optimize());
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x3B, 0x30, 0x01, 0x00, // cookie 12347, 2 containers
+            0b0000_0001,            // only the first container is a run container
+            0x00, 0x00, 0x09, 0x00, // key 0, cardinality 10
+            0x02, 0x00, 0x00, 0x00, // key 2, cardinality 1
+            0x01, 0x00, 0x00, 0x00, 0x09, 0x00, // 1 run: 0 and 9 more
+            0x05, 0x00,
+        ];
+        let mut bytes = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        let deserialized = RoaringBitmap::deserialize_from(&expected[..]).unwrap();
+        assert!(deserialized.iter().eq((0..10).chain([2 * 65536 + 5])));
+        assert!(matches!(deserialized.data[0].container, ContainerType::ContainerTypeRun(_)));
+    }
+
+    #[test]
+    fn run_containers_with_offsets_match_spec() {
+        let mut bitmap = RoaringBitmap::new();
+        bitmap.insert_range(100..=300);
+        bitmap.add(65536 + 7);
+        for i in (0..65536).step_by(2) {
+            bitmap.add(2 * 65536 + i);
+        }
+        bitmap.add(3 * 65536 + 65535);
+        bitmap.add(4 * 65536);
+        assert!(bitmap.run_optimize());
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&[0x3B, 0x30, 0x04, 0x00]); // cookie 12347, 5 containers
+        expected.push(0b0000_0001);
+        for (key, cardinality) in [(0u16, 201u16), (1, 1), (2, 32768), (3, 1), (4, 1)] {
+            expected.extend_from_slice(&key.to_le_bytes());
+            expected.extend_from_slice(&(cardinality - 1).to_le_bytes());
+        }
+        let header_size = 4 + 1 + 5 * 4 + 5 * 4;
+        for offset in [header_size, header_size + 6, header_size + 8, header_size + 8 + 8192, header_size + 8 + 8192 + 2] {
+            expected.extend_from_slice(&(offset as u32).to_le_bytes());
+        }
+        expected.extend_from_slice(&[0x01, 0x00, 0x64, 0x00, 0xC8, 0x00]); // 1 run: 100 and 200 more
+        expected.extend_from_slice(&[0x07, 0x00]);
+        for _ in 0..1024 {
+            expected.extend_from_slice(&0x5555_5555_5555_5555u64.to_le_bytes());
+        }
+        expected.extend_from_slice(&[0xFF, 0xFF]);
+        expected.extend_from_slice(&[0x00, 0x00]);
+
+        let mut bytes = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(bitmap.serialized_size(), expected.len());
+        let deserialized = RoaringBitmap::deserialize_from(&expected[..]).unwrap();
+        assert!(deserialized.iter().eq(bitmap.iter()));
+    }
+
+    #[test]
+    fn round_trips_every_container_type() {
+        let mut bitmap = RoaringBitmap::new();
+        assert_eq!(round_trip(&bitmap).len(), 0);
+        assert_eq!(bitmap.serialized_size(), 8);
+
+        for i in (0..100_000).step_by(3) {
+            bitmap.add(i);
+        }
+        bitmap.insert_range(1_000_000..2_000_000);
+        bitmap.add(u32::MAX);
+        let deserialized = round_trip(&bitmap);
+        assert!(deserialized.iter().eq(bitmap.iter()));
+
+        bitmap.run_optimize();
+        let deserialized = round_trip(&bitmap);
+        assert!(deserialized.iter().eq(bitmap.iter()));
+        assert_eq!(deserialized.len(), bitmap.len());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(RoaringBitmap::deserialize_from(&[0x00, 0x00, 0x00, 0x00][..]).is_err());
+        assert!(RoaringBitmap::deserialize_from(&[0x3A, 0x30, 0x00][..]).is_err());
+
+        let bitmap: RoaringBitmap = [1, 2, 3].into_iter().collect();
+        let mut bytes = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(RoaringBitmap::deserialize_from(&bytes[..]).is_err());
+
+        #[rustfmt::skip]
+        let unsorted: Vec<u8> = vec![
+            0x3A, 0x30, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x01, 0x00,
+            0x10, 0x00, 0x00, 0x00,
+            0x03, 0x00, 0x01, 0x00,
+        ];
+        assert_eq!(RoaringBitmap::deserialize_from(&unsorted[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        #[rustfmt::skip]
+        let touching_runs: Vec<u8> = vec![
+            0x3B, 0x30, 0x00, 0x00, 0b0000_0001,
+            0x00, 0x00, 0x02, 0x00,
+            0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(RoaringBitmap::deserialize_from(&touching_runs[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut separate_runs = touching_runs.clone();
+        separate_runs[15] = 0x03;
+        assert!(RoaringBitmap::deserialize_from(&separate_runs[..]).unwrap().iter().eq([0, 1, 3]));
+    }
+}
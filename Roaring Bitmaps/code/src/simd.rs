@@ -0,0 +1,259 @@
+// Vectorized container kernels for x86_64, picked at runtime with CPU feature
+// detection. Other architectures and older CPUs use the scalar kernels on the
+// containers, which also finish off whatever the vectorized loops leave over.
+use crate::{ArrayContainer, BitmapContainer};
+
+// Appends the values found in both sorted arrays to `res`.
+pub(crate) fn intersect(lhs: &[u16], rhs: &[u16], res: &mut Vec<u16>) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports SSE4.2.
+        return unsafe { x86::intersect_sse42(lhs, rhs, res) };
+    }
+    ArrayContainer::intersect_merge(lhs, rhs, res)
+}
+
+// Appends the values found in either sorted array to `res`, which must be empty.
+pub(crate) fn union(lhs: &[u16], rhs: &[u16], res: &mut Vec<u16>) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports SSE4.2.
+        return unsafe { x86::union_sse42(lhs, rhs, res) };
+    }
+    ArrayContainer::union_merge(lhs, rhs, res)
+}
+
+// Number of set bits in a bitmap container's words.
+pub(crate) fn popcount(bitmap: &[u64; 1024]) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2.
+        return unsafe { x86::popcount_avx2(bitmap) };
+    }
+    BitmapContainer::popcount(bitmap)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use crate::ArrayContainer;
+
+    unsafe fn load(array: &[u16], index: usize) -> __m128i {
+        debug_assert!(index + 8 <= array.len());
+        _mm_loadu_si128(array.as_ptr().add(index).cast())
+    }
+
+    // Compares a block of eight values from each array with a single string
+    // comparison, which flags the values of the `rhs` block that equal any value of
+    // the `lhs` block. The block with the smaller maximum is then replaced.
+    #[target_feature(enable = "sse4.2")]
+    pub(super) unsafe fn intersect_sse42(lhs: &[u16], rhs: &[u16], res: &mut Vec<u16>) {
+        const MODE: i32 = _SIDD_UWORD_OPS | _SIDD_CMP_EQUAL_ANY | _SIDD_BIT_MASK;
+        let mut lhs_idx = 0;
+        let mut rhs_idx = 0;
+        while lhs_idx + 8 <= lhs.len() && rhs_idx + 8 <= rhs.len() {
+            let matches = _mm_cmpestrm::<MODE>(load(lhs, lhs_idx), 8, load(rhs, rhs_idx), 8);
+            let mut mask = _mm_cvtsi128_si32(matches) as u32;
+            while mask != 0 {
+                res.push(rhs[rhs_idx + mask.trailing_zeros() as usize]);
+                mask &= mask - 1;
+            }
+            let lhs_max = lhs[lhs_idx + 7];
+            let rhs_max = rhs[rhs_idx + 7];
+            if lhs_max <= rhs_max {
+                lhs_idx += 8;
+            }
+            if rhs_max <= lhs_max {
+                rhs_idx += 8;
+            }
+        }
+        ArrayContainer::intersect_merge(&lhs[lhs_idx..], &rhs[rhs_idx..], res);
+    }
+
+    // Merges two sorted blocks of eight into their eight smallest and eight largest
+    // values, both sorted, by rotating the minimums against the maximums.
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn merge_blocks(lhs: __m128i, rhs: __m128i) -> (__m128i, __m128i) {
+        let mut min = _mm_min_epu16(lhs, rhs);
+        let mut max = _mm_max_epu16(lhs, rhs);
+        for _ in 0..7 {
+            let rotated = _mm_alignr_epi8::<2>(min, min);
+            min = _mm_min_epu16(rotated, max);
+            max = _mm_max_epu16(rotated, max);
+        }
+        (_mm_alignr_epi8::<2>(min, min), max)
+    }
+
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn push_unique(res: &mut Vec<u16>, block: __m128i) {
+        let mut values = [0u16; 8];
+        _mm_storeu_si128(values.as_mut_ptr().cast(), block);
+        for value in values {
+            if res.last() != Some(&value) {
+                res.push(value);
+            }
+        }
+    }
+
+    // Keeps the eight largest values seen so far in a register and merges the next
+    // block from whichever array has the smaller head into it. The eight smallest
+    // values of each merge are never larger than anything still to come, so they
+    // can be written out, skipping the duplicates the merge puts next to each other.
+    #[target_feature(enable = "sse4.2")]
+    pub(super) unsafe fn union_sse42(lhs: &[u16], rhs: &[u16], res: &mut Vec<u16>) {
+        if lhs.len() < 8 || rhs.len() < 8 {
+            return ArrayContainer::union_merge(lhs, rhs, res);
+        }
+        let (min, mut max) = merge_blocks(load(lhs, 0), load(rhs, 0));
+        push_unique(res, min);
+        let mut lhs_idx = 8;
+        let mut rhs_idx = 8;
+        while lhs_idx + 8 <= lhs.len() && rhs_idx + 8 <= rhs.len() {
+            let next = if lhs[lhs_idx] <= rhs[rhs_idx] {
+                lhs_idx += 8;
+                load(lhs, lhs_idx - 8)
+            } else {
+                rhs_idx += 8;
+                load(rhs, rhs_idx - 8)
+            };
+            let (min, next_max) = merge_blocks(next, max);
+            push_unique(res, min);
+            max = next_max;
+        }
+
+        let mut pending = [0u16; 8];
+        _mm_storeu_si128(pending.as_mut_ptr().cast(), max);
+        let mut lhs_rest = Vec::with_capacity(8 + lhs.len() - lhs_idx);
+        ArrayContainer::union_merge(&pending, &lhs[lhs_idx..], &mut lhs_rest);
+        let mut rest = Vec::with_capacity(lhs_rest.len() + rhs.len() - rhs_idx);
+        ArrayContainer::union_merge(&lhs_rest, &rhs[rhs_idx..], &mut rest);
+        for value in rest {
+            if res.last() != Some(&value) {
+                res.push(value);
+            }
+        }
+    }
+
+    // Counts the bits of every nibble with a 16-entry lookup table, then sums the
+    // byte counts of each 64-bit lane against zero.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn popcount_avx2(bitmap: &[u64; 1024]) -> usize {
+        let lookup = _mm256_setr_epi8(
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+        );
+        let low_mask = _mm256_set1_epi8(0x0f);
+        let mut total = _mm256_setzero_si256();
+        for words in bitmap.chunks_exact(4) {
+            let words = _mm256_loadu_si256(words.as_ptr().cast());
+            let low = _mm256_and_si256(words, low_mask);
+            let high = _mm256_and_si256(_mm256_srli_epi16::<4>(words), low_mask);
+            let counts = _mm256_add_epi8(_mm256_shuffle_epi8(lookup, low), _mm256_shuffle_epi8(lookup, high));
+            total = _mm256_add_epi64(total, _mm256_sad_epu8(counts, _mm256_setzero_si256()));
+        }
+        let mut lanes = [0u64; 4];
+        _mm256_storeu_si256(lanes.as_mut_ptr().cast(), total);
+        lanes.iter().sum::<u64>() as usize
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    // xorshift64, so the inputs are reproducible without a dependency.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // A sorted array of unique values, each kept with probability `density`/256
+        // within a window starting at `offset`.
+        fn sorted_array(&mut self, offset: u32, window: u32, density: u64) -> Vec<u16> {
+            (offset..(offset + window).min(1 << 16)).filter(|_| self.next() % 256 < density).map(|value| value as u16).collect()
+        }
+    }
+
+    fn array_pairs() -> Vec<(Vec<u16>, Vec<u16>)> {
+        let mut random = Random(0x9E37_79B9_7F4A_7C15);
+        let mut pairs = vec![
+            (Vec::new(), Vec::new()),
+            ((0..8).collect(), (0..8).collect()),
+            ((0..8).collect(), (8..16).collect()),
+            ((0..4096).collect(), (0..4096).map(|value| value * 16).collect()),
+            (vec![u16::MAX - 8, u16::MAX - 7, u16::MAX - 6, u16::MAX - 5, u16::MAX - 4, u16::MAX - 3, u16::MAX - 2, u16::MAX - 1, u16::MAX], (u16::MAX - 20..=u16::MAX).collect()),
+        ];
+        for _ in 0..200 {
+            let window = 1 + (random.next() % 65536) as u32;
+            let lhs_offset = (random.next() % 65536) as u32;
+            let rhs_offset = (random.next() % 65536) as u32;
+            let lhs_density = random.next() % 256;
+            let rhs_density = random.next() % 256;
+            let lhs = random.sorted_array(lhs_offset, window, lhs_density);
+            let rhs = random.sorted_array(rhs_offset.min(lhs_offset + window / 2), window, rhs_density);
+            pairs.push((lhs, rhs));
+        }
+        pairs
+    }
+
+    #[test]
+    fn array_kernels_match_scalar() {
+        let sse42 = is_x86_feature_detected!("sse4.2");
+        for (lhs, rhs) in array_pairs() {
+            for (lhs, rhs) in [(&lhs, &rhs), (&rhs, &lhs)] {
+                let mut expected = Vec::new();
+                ArrayContainer::intersect_merge(lhs, rhs, &mut expected);
+                let mut actual = Vec::new();
+                intersect(lhs, rhs, &mut actual);
+                assert_eq!(actual, expected);
+                if sse42 {
+                    let mut actual = Vec::new();
+                    unsafe { x86::intersect_sse42(lhs, rhs, &mut actual) };
+                    assert_eq!(actual, expected);
+                }
+
+                let mut expected = Vec::new();
+                ArrayContainer::union_merge(lhs, rhs, &mut expected);
+                let mut actual = Vec::new();
+                union(lhs, rhs, &mut actual);
+                assert_eq!(actual, expected);
+                if sse42 {
+                    let mut actual = Vec::new();
+                    unsafe { x86::union_sse42(lhs, rhs, &mut actual) };
+                    assert_eq!(actual, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn popcount_matches_scalar() {
+        let avx2 = is_x86_feature_detected!("avx2");
+        let mut random = Random(0x2545_F491_4F6C_DD1D);
+        let mut bitmaps = vec![Box::new([0u64; 1024]), Box::new([u64::MAX; 1024])];
+        for _ in 0..50 {
+            let sparsity = random.next() % 4;
+            let mut bitmap = Box::new([0u64; 1024]);
+            for word in bitmap.iter_mut() {
+                *word = random.next();
+                for _ in 0..sparsity {
+                    *word &= random.next();
+                }
+            }
+            bitmaps.push(bitmap);
+        }
+        for bitmap in &bitmaps {
+            let expected = BitmapContainer::popcount(bitmap);
+            assert_eq!(popcount(bitmap), expected);
+            if avx2 {
+                assert_eq!(unsafe { x86::popcount_avx2(bitmap) }, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// Construction from values that are already sorted, which can fill each container
+// by appending instead of searching for every value's position.
+use std::fmt;
+
+use crate::{ArrayContainer, BitmapContainer, Container, ContainerType, RoaringBitmap};
+
+/// The error returned by [`RoaringBitmap::from_sorted_iter`] when a value is
+/// smaller than the one before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonSortedError {
+    valid_until: u64,
+}
+
+impl NonSortedError {
+    /// Returns the number of values that were read in order before the first one
+    /// that was not.
+    pub fn valid_until(&self) -> u64 {
+        self.valid_until
+    }
+}
+
+impl fmt::Display for NonSortedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "values are not sorted: the value at position {} is smaller than the one before it", self.valid_until)
+    }
+}
+
+impl std::error::Error for NonSortedError {}
+
+impl RoaringBitmap {
+    /// Builds a bitmap from values in ascending order. Repeated values are allowed
+    /// and kept once. Returns an error at the first value smaller than the previous
+    /// one.
+    pub fn from_sorted_iter<I: IntoIterator<Item = u32>>(iter: I) -> Result<Self, NonSortedError> {
+        let mut res = RoaringBitmap::new();
+        let mut chunk: Option<ChunkBuilder> = None;
+        let mut previous: Option<u32> = None;
+        for (index, value) in iter.into_iter().enumerate() {
+            match previous {
+                Some(previous) if value < previous => return Err(NonSortedError { valid_until: index as u64 }),
+                Some(previous) if value == previous => continue,
+                _ => previous = Some(value),
+            }
+            let most_significant_bits = (value >> 16) as u16;
+            let builder = match &mut chunk {
+                Some(builder) if builder.most_significant_bits == most_significant_bits => builder,
+                _ => {
+                    if let Some(builder) = chunk.take() {
+                        res.data.push(builder.finish());
+                    }
+                    chunk.insert(ChunkBuilder::new(most_significant_bits))
+                }
+            };
+            builder.push(value as u16);
+        }
+        if let Some(builder) = chunk {
+            res.data.push(builder.finish());
+        }
+        res.debug_validate(0..res.data.len());
+        Ok(res)
+    }
+
+    /// Builds a bitmap from a slice of values in ascending order, like
+    /// [`RoaringBitmap::from_sorted_iter`].
+    pub fn from_sorted_slice(values: &[u32]) -> Result<Self, NonSortedError> {
+        Self::from_sorted_iter(values.iter().copied())
+    }
+}
+
+// Collects the ascending, unique low bits of one chunk. Values go into an array
+// until there are too many for one, and straight into the words after that.
+struct ChunkBuilder {
+    most_significant_bits: u16,
+    array: Vec<u16>,
+    bitmap: Option<BitmapContainer>,
+}
+
+impl ChunkBuilder {
+    fn new(most_significant_bits: u16) -> Self {
+        ChunkBuilder { most_significant_bits, array: Vec::new(), bitmap: None }
+    }
+
+    fn push(&mut self, value: u16) {
+        match &mut self.bitmap {
+            Some(bitmap_container) => {
+                let (index, offset) = BitmapContainer::get_index_and_offset(&value);
+                bitmap_container.bitmap[index] |= 1 << offset;
+                bitmap_container.cardinality += 1;
+            }
+            None if self.array.len() == 4096 => {
+                let array_container = ArrayContainer { most_significant_bits: self.most_significant_bits, array: std::mem::take(&mut self.array) };
+                let mut bitmap_container = BitmapContainer::from(array_container);
+                bitmap_container.add(value);
+                self.bitmap = Some(bitmap_container);
+            }
+            None => self.array.push(value),
+        }
+    }
+
+    fn finish(self) -> Container {
+        let container = match self.bitmap {
+            Some(bitmap_container) => ContainerType::ContainerTypeBitmap(bitmap_container),
+            None => ContainerType::ContainerTypeArray(ArrayContainer { most_significant_bits: self.most_significant_bits, array: self.array }),
+        };
+        Container { most_significant_bits: self.most_significant_bits, container }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_same_bitmap_as_adding_values() {
+        let values: Vec<u32> = (0..10)
+            .chain(1000..6000)
+            .chain((70_000..200_000).step_by(3))
+            .chain(300_000..304_096)
+            .chain(400_000..404_097)
+            .chain([u32::MAX - 1, u32::MAX])
+            .collect();
+        let bitmap = RoaringBitmap::from_sorted_slice(&values).unwrap();
+        assert_eq!(bitmap.validate(), Ok(()));
+        assert!(bitmap.iter().eq(values.iter().copied()));
+
+        let mut expected = RoaringBitmap::new();
+        for &value in &values {
+            expected.add(value);
+        }
+        assert_eq!(bitmap.data.len(), expected.data.len());
+        for (container, expected_container) in bitmap.data.iter().zip(&expected.data) {
+            assert_eq!(container.most_significant_bits, expected_container.most_significant_bits);
+            assert_eq!(
+                std::mem::discriminant(&container.container),
+                std::mem::discriminant(&expected_container.container)
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_repeated_values_once() {
+        let bitmap = RoaringBitmap::from_sorted_iter([1, 1, 2, 70_000, 70_000, 70_000]).unwrap();
+        assert!(bitmap.iter().eq([1, 2, 70_000]));
+        assert!(RoaringBitmap::from_sorted_iter([]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unsorted_values() {
+        let error = RoaringBitmap::from_sorted_iter([1, 5, 70_000, 4]).unwrap_err();
+        assert_eq!(error.valid_until(), 3);
+        assert_eq!(RoaringBitmap::from_sorted_slice(&[2, 1]).unwrap_err().valid_until(), 1);
+    }
+}
@@ -0,0 +1,321 @@
+// A 64-bit bitmap made of one `RoaringBitmap` per distinct value of the high 32
+// bits, the same layout the Java, C and Go implementations use for their 64-bit
+// portable format.
+use std::collections::btree_map::{self, BTreeMap, Entry};
+use std::io::{self, Read, Write};
+
+use crate::{Iter, RoaringBitmap};
+
+/// A compressed set of `u64` values.
+#[derive(Debug)]
+pub struct RoaringTreemap {
+    // Keyed by the high 32 bits. Bitmaps that become empty are dropped.
+    map: BTreeMap<u32, RoaringBitmap>,
+}
+
+impl RoaringTreemap {
+    /// Creates an empty treemap.
+    pub fn new() -> Self {
+        RoaringTreemap { map: BTreeMap::new() }
+    }
+
+    /// Adds `value` to the treemap.
+    pub fn add(&mut self, value: u64) {
+        let (high_bits, low_bits) = split(value);
+        self.map.entry(high_bits).or_default().add(low_bits);
+    }
+
+    /// Returns `true` if `value` is in the treemap.
+    pub fn contains(&self, value: u64) -> bool {
+        let (high_bits, low_bits) = split(value);
+        self.map.get(&high_bits).is_some_and(|bitmap| bitmap.contains(low_bits))
+    }
+
+    /// Removes `value` from the treemap, if present.
+    pub fn remove(&mut self, value: u64) {
+        let (high_bits, low_bits) = split(value);
+        if let Entry::Occupied(mut entry) = self.map.entry(high_bits) {
+            entry.get_mut().remove(low_bits);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+
+    /// Returns the values that are in `self`, `rhs` or both.
+    pub fn union(&self, rhs: &RoaringTreemap) -> Self {
+        let mut res = self.clone();
+        for (&high_bits, rhs_bitmap) in &rhs.map {
+            match res.map.entry(high_bits) {
+                Entry::Occupied(mut entry) => entry.get_mut().union_with(rhs_bitmap),
+                Entry::Vacant(entry) => {
+                    entry.insert(rhs_bitmap.clone());
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the values that are in both `self` and `rhs`.
+    pub fn intersection(&self, rhs: &RoaringTreemap) -> Self {
+        let mut res = RoaringTreemap::new();
+        for (&high_bits, lhs_bitmap) in &self.map {
+            if let Some(rhs_bitmap) = rhs.map.get(&high_bits) {
+                let bitmap = lhs_bitmap.intersection(rhs_bitmap);
+                if !bitmap.is_empty() {
+                    res.map.insert(high_bits, bitmap);
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the values that are in `self` but not in `rhs`.
+    pub fn difference(&self, rhs: &RoaringTreemap) -> Self {
+        let mut res = RoaringTreemap::new();
+        for (&high_bits, lhs_bitmap) in &self.map {
+            let bitmap = match rhs.map.get(&high_bits) {
+                Some(rhs_bitmap) => lhs_bitmap.difference(rhs_bitmap),
+                None => lhs_bitmap.clone(),
+            };
+            if !bitmap.is_empty() {
+                res.map.insert(high_bits, bitmap);
+            }
+        }
+        res
+    }
+
+    /// Returns an iterator over the values of the treemap in ascending order.
+    pub fn iter(&self) -> TreemapIter<'_> {
+        TreemapIter {
+            bitmaps: self.map.iter(),
+            current: None,
+        }
+    }
+
+    /// Returns `true` if the treemap holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of values in the treemap.
+    pub fn len(&self) -> u64 {
+        self.map.values().map(RoaringBitmap::len).sum()
+    }
+
+    /// Returns the number of values in the treemap that are less than or equal to `value`.
+    pub fn rank(&self, value: u64) -> u64 {
+        let (high_bits, low_bits) = split(value);
+        let preceding_len: u64 = self.map.range(..high_bits).map(|(_, bitmap)| bitmap.len()).sum();
+        preceding_len + self.map.get(&high_bits).map_or(0, |bitmap| bitmap.rank(low_bits))
+    }
+
+    /// Returns the `n`-th smallest value in the treemap, counting from zero, or `None`
+    /// if the treemap holds `n` values or fewer.
+    pub fn select(&self, n: u64) -> Option<u64> {
+        let mut remaining = n;
+        for (&high_bits, bitmap) in &self.map {
+            let len = bitmap.len();
+            if remaining < len {
+                return bitmap.select(remaining).map(|low_bits| join(high_bits, low_bits));
+            }
+            remaining -= len;
+        }
+        None
+    }
+
+    /// Writes the treemap to `writer` in the portable 64-bit Roaring format: the
+    /// number of bitmaps, then each bitmap preceded by its high 32 bits.
+    pub fn serialize_into<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&(self.map.len() as u64).to_le_bytes())?;
+        for (high_bits, bitmap) in &self.map {
+            writer.write_all(&high_bits.to_le_bytes())?;
+            bitmap.serialize_into(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a treemap written in the portable 64-bit Roaring format from `reader`.
+    pub fn deserialize_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = [0u8; 8];
+        reader.read_exact(&mut bytes)?;
+        let size = u64::from_le_bytes(bytes);
+        let mut res = RoaringTreemap::new();
+        let mut previous = None;
+        for _ in 0..size {
+            let mut bytes = [0u8; 4];
+            reader.read_exact(&mut bytes)?;
+            let high_bits = u32::from_le_bytes(bytes);
+            if previous.is_some_and(|previous| previous >= high_bits) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bitmap keys are not strictly increasing"));
+            }
+            previous = Some(high_bits);
+            let bitmap = RoaringBitmap::deserialize_from(&mut reader)?;
+            if !bitmap.is_empty() {
+                res.map.insert(high_bits, bitmap);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Returns the number of bytes [`RoaringTreemap::serialize_into`] writes.
+    pub fn serialized_size(&self) -> usize {
+        8 + self.map.values().map(|bitmap| 4 + bitmap.serialized_size()).sum::<usize>()
+    }
+}
+
+fn split(value: u64) -> (u32, u32) {
+    ((value >> 32) as u32, value as u32)
+}
+
+fn join(high_bits: u32, low_bits: u32) -> u64 {
+    (high_bits as u64) << 32 | low_bits as u64
+}
+
+impl Default for RoaringTreemap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for RoaringTreemap {
+    fn clone(&self) -> Self {
+        RoaringTreemap { map: self.map.clone() }
+    }
+}
+
+impl<'a> IntoIterator for &'a RoaringTreemap {
+    type Item = u64;
+    type IntoIter = TreemapIter<'a>;
+
+    fn into_iter(self) -> TreemapIter<'a> {
+        self.iter()
+    }
+}
+
+impl FromIterator<u64> for RoaringTreemap {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Self {
+        let mut treemap = RoaringTreemap::new();
+        for value in iter {
+            treemap.add(value);
+        }
+        treemap
+    }
+}
+
+/// An iterator over the values of a borrowed [`RoaringTreemap`], in ascending order.
+pub struct TreemapIter<'a> {
+    bitmaps: btree_map::Iter<'a, u32, RoaringBitmap>,
+    current: Option<(u32, Iter<'a>)>,
+}
+
+impl Iterator for TreemapIter<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            if let Some((high_bits, values)) = &mut self.current {
+                if let Some(low_bits) = values.next() {
+                    return Some(join(*high_bits, low_bits));
+                }
+            }
+            let (&high_bits, bitmap) = self.bitmaps.next()?;
+            self.current = Some((high_bits, bitmap.iter()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn sample() -> (BTreeSet<u64>, RoaringTreemap) {
+        let mut expected = BTreeSet::new();
+        for i in 0..5000u64 {
+            expected.insert(i * 3);
+            expected.insert((7 << 32) + i);
+            expected.insert(u64::MAX - i * 1000);
+        }
+        expected.insert(u32::MAX as u64);
+        expected.insert(1 << 32);
+        let treemap = expected.iter().copied().collect();
+        (expected, treemap)
+    }
+
+    #[test]
+    fn add_remove_contains() {
+        let (expected, mut treemap) = sample();
+        assert_eq!(treemap.len(), expected.len() as u64);
+        assert!(treemap.iter().eq(expected.iter().copied()));
+        assert!(treemap.contains(1 << 32) && treemap.contains(u64::MAX) && !treemap.contains(2 << 32));
+
+        treemap.remove(1 << 32);
+        treemap.remove(1 << 32);
+        treemap.remove(3 << 40);
+        assert!(!treemap.contains(1 << 32));
+        assert!(!treemap.map.contains_key(&1));
+        assert_eq!(treemap.len(), expected.len() as u64 - 1);
+
+        for value in expected {
+            treemap.remove(value);
+        }
+        assert!(treemap.is_empty());
+        assert_eq!(treemap.iter().next(), None);
+    }
+
+    #[test]
+    fn set_operations() {
+        let (lhs_expected, lhs) = sample();
+        let rhs_expected: BTreeSet<u64> = (0..20_000u64).step_by(2).chain((7 << 32)..(7 << 32) + 100).chain([5 << 32]).collect();
+        let rhs: RoaringTreemap = rhs_expected.iter().copied().collect();
+
+        assert!(lhs.union(&rhs).iter().eq(lhs_expected.union(&rhs_expected).copied()));
+        assert!(lhs.intersection(&rhs).iter().eq(lhs_expected.intersection(&rhs_expected).copied()));
+        assert!(lhs.difference(&rhs).iter().eq(lhs_expected.difference(&rhs_expected).copied()));
+        assert!(rhs.difference(&lhs).iter().eq(rhs_expected.difference(&lhs_expected).copied()));
+        assert!(!lhs.intersection(&rhs).map.contains_key(&5));
+        assert!(lhs.difference(&lhs).is_empty());
+    }
+
+    #[test]
+    fn rank_and_select() {
+        let (expected, treemap) = sample();
+        for (index, &value) in expected.iter().enumerate() {
+            assert_eq!(treemap.select(index as u64), Some(value));
+            assert_eq!(treemap.rank(value), index as u64 + 1);
+        }
+        assert_eq!(treemap.select(expected.len() as u64), None);
+        assert_eq!(treemap.rank(2 << 32), expected.range(..2 << 32).count() as u64);
+        assert_eq!(treemap.rank(u64::MAX), expected.len() as u64);
+    }
+
+    #[test]
+    fn serialization_matches_spec() {
+        let treemap: RoaringTreemap = [5, (1 << 32) + 7].into_iter().collect();
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 2 bitmaps
+            0x00, 0x00, 0x00, 0x00, // high bits 0
+            0x3A, 0x30, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00,
+            0x05, 0x00,
+            0x01, 0x00, 0x00, 0x00, // high bits 1
+            0x3A, 0x30, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00,
+            0x07, 0x00,
+        ];
+        let mut bytes = Vec::new();
+        treemap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(treemap.serialized_size(), expected.len());
+        assert!(RoaringTreemap::deserialize_from(&expected[..]).unwrap().iter().eq([5, (1 << 32) + 7]));
+
+        let (expected, treemap) = sample();
+        let mut bytes = Vec::new();
+        treemap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), treemap.serialized_size());
+        assert!(RoaringTreemap::deserialize_from(&bytes[..]).unwrap().iter().eq(expected.iter().copied()));
+        assert!(RoaringTreemap::deserialize_from(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+// Consistency checks for the internal representation of a bitmap. Debug builds run
+// them after every mutation, over the containers the mutation touched.
+use std::fmt;
+use std::ops::Range;
+
+use crate::{BitmapContainer, Container, ContainerType, RoaringBitmap};
+
+/// A broken internal invariant of a [`RoaringBitmap`], as reported by
+/// [`RoaringBitmap::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// The container at `index` does not have a larger key than the one before it.
+    UnsortedContainers { index: usize },
+    /// A container holds no values.
+    EmptyContainer { most_significant_bits: u16 },
+    /// A container's own copy of its key differs from the key it is stored under.
+    MismatchedKey { most_significant_bits: u16, stored: u16 },
+    /// An array container's values are not sorted and unique.
+    UnsortedArray { most_significant_bits: u16 },
+    /// An array container holds more than 4096 values.
+    OversizedArray { most_significant_bits: u16, cardinality: usize },
+    /// A bitmap container holds 4096 values or fewer.
+    UndersizedBitmap { most_significant_bits: u16, cardinality: usize },
+    /// A bitmap or run container's cached cardinality differs from the number of
+    /// values it holds.
+    StaleCardinality { most_significant_bits: u16, cached: usize, actual: usize },
+    /// A run container's runs overflow, overlap, touch or are out of order.
+    MalformedRuns { most_significant_bits: u16 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::UnsortedContainers { index } => write!(f, "container {index} is not sorted after its predecessor"),
+            Self::EmptyContainer { most_significant_bits } => write!(f, "container {most_significant_bits} is empty"),
+            Self::MismatchedKey { most_significant_bits, stored } => {
+                write!(f, "container {most_significant_bits} stores the key {stored}")
+            }
+            Self::UnsortedArray { most_significant_bits } => {
+                write!(f, "array container {most_significant_bits} is not sorted and unique")
+            }
+            Self::OversizedArray { most_significant_bits, cardinality } => {
+                write!(f, "array container {most_significant_bits} holds {cardinality} values, more than 4096")
+            }
+            Self::UndersizedBitmap { most_significant_bits, cardinality } => {
+                write!(f, "bitmap container {most_significant_bits} holds {cardinality} values, at most 4096")
+            }
+            Self::StaleCardinality { most_significant_bits, cached, actual } => {
+                write!(f, "container {most_significant_bits} caches a cardinality of {cached} but holds {actual} values")
+            }
+            Self::MalformedRuns { most_significant_bits } => write!(f, "run container {most_significant_bits} has malformed runs"),
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+impl RoaringBitmap {
+    /// Checks the internal invariants of the bitmap: containers are sorted by key
+    /// and never empty, and each container is well formed for its type.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        self.validate_containers(0..self.data.len())
+    }
+
+    // Checks the containers in `range`, and that every container from `range.start`
+    // up to and including `range.end` has a larger key than the one before it, so an
+    // empty range still checks the ordering around a removed container.
+    fn validate_containers(&self, range: Range<usize>) -> Result<(), InvariantViolation> {
+        for index in range.start.max(1)..(range.end + 1).min(self.data.len()) {
+            if self.data[index - 1].most_significant_bits >= self.data[index].most_significant_bits {
+                return Err(InvariantViolation::UnsortedContainers { index });
+            }
+        }
+        self.data[range].iter().try_for_each(Container::validate)
+    }
+
+    // Called at the end of every mutating operation with the indices of the
+    // containers it may have changed.
+    pub(crate) fn debug_validate(&self, range: Range<usize>) {
+        if cfg!(debug_assertions) {
+            if let Err(violation) = self.validate_containers(range) {
+                panic!("bitmap invariant violated: {violation}");
+            }
+        }
+    }
+
+    // Like `debug_validate`, for operations that only change the containers stored
+    // under `keys`. A key without a container still has the ordering around the
+    // place it would go checked.
+    pub(crate) fn debug_validate_keys<I: IntoIterator<Item = u16>>(&self, keys: I) {
+        if cfg!(debug_assertions) {
+            for key in keys {
+                match self.data.binary_search_by_key(&key, |container| container.most_significant_bits) {
+                    Ok(index) => self.debug_validate(index..index + 1),
+                    Err(index) => self.debug_validate(index..index),
+                }
+            }
+        }
+    }
+}
+
+impl Container {
+    fn validate(&self) -> Result<(), InvariantViolation> {
+        let most_significant_bits = self.most_significant_bits;
+        if self.container.is_empty() {
+            return Err(InvariantViolation::EmptyContainer { most_significant_bits });
+        }
+        match &self.container {
+            ContainerType::ContainerTypeArray(array_container) => {
+                if array_container.most_significant_bits != most_significant_bits {
+                    return Err(InvariantViolation::MismatchedKey { most_significant_bits, stored: array_container.most_significant_bits });
+                }
+                if array_container.array.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(InvariantViolation::UnsortedArray { most_significant_bits });
+                }
+                if array_container.array.len() > 4096 {
+                    return Err(InvariantViolation::OversizedArray { most_significant_bits, cardinality: array_container.array.len() });
+                }
+            }
+            ContainerType::ContainerTypeBitmap(bitmap_container) => {
+                if bitmap_container.most_significant_bits != most_significant_bits {
+                    return Err(InvariantViolation::MismatchedKey { most_significant_bits, stored: bitmap_container.most_significant_bits });
+                }
+                // The scalar count, so the SIMD kernel that keeps the cardinality is checked
+                // against something else, and debug builds don't pay for unoptimized intrinsics.
+                let actual = BitmapContainer::popcount(&bitmap_container.bitmap);
+                if bitmap_container.cardinality != actual {
+                    return Err(InvariantViolation::StaleCardinality { most_significant_bits, cached: bitmap_container.cardinality, actual });
+                }
+                if actual <= 4096 {
+                    return Err(InvariantViolation::UndersizedBitmap { most_significant_bits, cardinality: actual });
+                }
+            }
+            ContainerType::ContainerTypeRun(run_container) => {
+                if run_container.most_significant_bits != most_significant_bits {
+                    return Err(InvariantViolation::MismatchedKey { most_significant_bits, stored: run_container.most_significant_bits });
+                }
+                let mut actual = 0;
+                let mut previous_end: Option<u32> = None;
+                for &(start, length) in &run_container.runs {
+                    let end = start as u32 + length as u32;
+                    if end > u16::MAX as u32 || previous_end.is_some_and(|previous_end| start as u32 <= previous_end + 1) {
+                        return Err(InvariantViolation::MalformedRuns { most_significant_bits });
+                    }
+                    actual += length as usize + 1;
+                    previous_end = Some(end);
+                }
+                if run_container.cardinality != actual {
+                    return Err(InvariantViolation::StaleCardinality { most_significant_bits, cached: run_container.cardinality, actual });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArrayContainer, BitmapContainer, RunContainer};
+
+    fn with_container(most_significant_bits: u16, container: ContainerType) -> RoaringBitmap {
+        RoaringBitmap { data: vec![Container { most_significant_bits, container }] }
+    }
+
+    #[test]
+    fn valid_bitmaps_pass() {
+        let mut bitmap: RoaringBitmap = (0..10_000).chain(100_000..100_010).chain([u32::MAX]).collect();
+        assert_eq!(bitmap.validate(), Ok(()));
+        bitmap.insert_range(200_000..300_000);
+        bitmap.run_optimize();
+        assert_eq!(bitmap.validate(), Ok(()));
+        assert_eq!(RoaringBitmap::new().validate(), Ok(()));
+    }
+
+    #[test]
+    fn detects_container_order_and_emptiness() {
+        let mut bitmap: RoaringBitmap = [1, 65536 + 1, 2 * 65536 + 1].into_iter().collect();
+        bitmap.data.swap(1, 2);
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::UnsortedContainers { index: 2 }));
+
+        let bitmap = with_container(3, ContainerType::ContainerTypeArray(ArrayContainer::new(3)));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::EmptyContainer { most_significant_bits: 3 }));
+
+        let mut array_container = ArrayContainer::new(0);
+        array_container.add(1);
+        let bitmap = with_container(3, ContainerType::ContainerTypeArray(array_container));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::MismatchedKey { most_significant_bits: 3, stored: 0 }));
+    }
+
+    #[test]
+    fn detects_malformed_arrays() {
+        let mut array_container = ArrayContainer::new(0);
+        array_container.array = vec![1, 3, 3];
+        let bitmap = with_container(0, ContainerType::ContainerTypeArray(array_container));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::UnsortedArray { most_significant_bits: 0 }));
+
+        let mut array_container = ArrayContainer::new(0);
+        array_container.array = (0..5000).collect();
+        let bitmap = with_container(0, ContainerType::ContainerTypeArray(array_container));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::OversizedArray { most_significant_bits: 0, cardinality: 5000 }));
+    }
+
+    #[test]
+    fn detects_malformed_bitmaps() {
+        let mut bitmap_container = BitmapContainer::from(ArrayContainer { most_significant_bits: 0, array: (0..4096).collect() });
+        bitmap_container.add(5000);
+        bitmap_container.cardinality += 1;
+        let bitmap = with_container(0, ContainerType::ContainerTypeBitmap(bitmap_container.clone()));
+        assert_eq!(
+            bitmap.validate(),
+            Err(InvariantViolation::StaleCardinality { most_significant_bits: 0, cached: 4098, actual: 4097 })
+        );
+
+        bitmap_container.cardinality -= 1;
+        bitmap_container.remove(&5000);
+        let bitmap = with_container(0, ContainerType::ContainerTypeBitmap(bitmap_container));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::UndersizedBitmap { most_significant_bits: 0, cardinality: 4096 }));
+    }
+
+    #[test]
+    fn detects_malformed_runs() {
+        let mut run_container = RunContainer::new(0);
+        run_container.runs = vec![(0, 9), (10, 5)];
+        run_container.cardinality = 16;
+        let bitmap = with_container(0, ContainerType::ContainerTypeRun(run_container));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::MalformedRuns { most_significant_bits: 0 }));
+
+        let mut run_container = RunContainer::new(0);
+        run_container.runs = vec![(0, 9), (20, 5)];
+        run_container.cardinality = 10;
+        let bitmap = with_container(0, ContainerType::ContainerTypeRun(run_container));
+        assert_eq!(bitmap.validate(), Err(InvariantViolation::StaleCardinality { most_significant_bits: 0, cached: 10, actual: 16 }));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "bitmap invariant violated")]
+    fn mutations_check_invariants_in_debug_builds() {
+        let mut array_container = ArrayContainer::new(0);
+        array_container.array = vec![3, 2];
+        let mut bitmap = with_container(0, ContainerType::ContainerTypeArray(array_container));
+        bitmap.add(1);
+    }
+}